@@ -0,0 +1,88 @@
+#![feature(test)]
+extern crate test;
+extern crate pyramid_animation;
+
+use test::{Bencher, black_box};
+use pyramid_animation::*;
+
+const KEYS: usize = 5000;
+const FRAME_TIME: f32 = 1.0 / 120.0;
+
+/// A mocap style curve, with a key every frame.
+fn dense_curve() -> LinearKeyFrameCurve<f32> {
+    LinearKeyFrameCurve::new((0..KEYS).map(|i| Key(i as f32 * FRAME_TIME, (i as f32 * 0.1).sin())).collect()).unwrap()
+}
+
+/// Times a curve is sampled at when played back at 60 fps, so each lookup lands two keys
+/// after the previous one. The cursor steps over them instead of binary searching.
+fn playback_times() -> Vec<f32> {
+    (0..KEYS / 2).map(|i| i as f32 / 60.0).collect()
+}
+
+/// The lookup `LinearKeyFrameCurve::value` used to do, scanning all keys, kept as a reference.
+fn linear_scan_value(curve: &LinearKeyFrameCurve<f32>, time: f32) -> f32 {
+    let keys = curve.keys();
+    let mut key_before = None;
+    let mut key_after = None;
+    for i in 0..keys.len() {
+        if keys[i].0 > time { break; }
+        else { key_before = Some(&keys[i]); }
+    }
+    for i in 0..keys.len() {
+        if keys[i].0 > time {
+            key_after = Some(&keys[i]);
+            break;
+        }
+    }
+    match (key_before, key_after) {
+        (None, _) => keys[0].1,
+        (Some(k), None) => k.1,
+        (Some(a), Some(b)) => a.1 + (b.1 - a.1) * (time - a.0) / (b.0 - a.0)
+    }
+}
+
+#[bench]
+fn bench_linear_scan(b: &mut Bencher) {
+    let curve = dense_curve();
+    let times = playback_times();
+    b.iter(|| {
+        for &time in &times {
+            black_box(linear_scan_value(&curve, time));
+        }
+    });
+}
+
+#[bench]
+fn bench_binary_search(b: &mut Bencher) {
+    let curve = dense_curve();
+    let times = playback_times();
+    b.iter(|| {
+        for &time in &times {
+            black_box(curve.value(time));
+        }
+    });
+}
+
+#[bench]
+fn bench_cursor(b: &mut Bencher) {
+    let curve = dense_curve();
+    let times = playback_times();
+    b.iter(|| {
+        let mut cursor = KeyCursor::new();
+        for &time in &times {
+            black_box(curve.value_with_cursor(time, &mut cursor));
+        }
+    });
+}
+
+#[bench]
+fn bench_cursor_random_access(b: &mut Bencher) {
+    let curve = dense_curve();
+    let times: Vec<f32> = playback_times().iter().enumerate().map(|(i, _)| ((i * 7919) % KEYS) as f32 * FRAME_TIME).collect();
+    b.iter(|| {
+        let mut cursor = KeyCursor::new();
+        for &time in &times {
+            black_box(curve.value_with_cursor(time, &mut cursor));
+        }
+    });
+}
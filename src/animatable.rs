@@ -30,6 +30,14 @@ impl Animatable {
             value: self.value.iter().map(|x| x * weight).collect()
         }
     }
+    /// Writes the value the way keys are written in PON. Unlike `to_pon`, this always reads
+    /// back into the same components.
+    pub fn to_key_pon(&self) -> Pon {
+        match self.value.len() {
+            1 => self.value[0].to_pon(),
+            _ => Pon::FloatArray(self.value.clone())
+        }
+    }
 }
 
 impl ToPon for Animatable {
@@ -38,7 +46,7 @@ impl ToPon for Animatable {
             1 => self.value[0].to_pon(),
             3 => Vector3::new(self.value[0], self.value[1], self.value[2]).to_pon(),
             4 => Vector4::new(self.value[3], self.value[0], self.value[1], self.value[2]).to_pon(),
-            _ => unreachable!()
+            _ => Pon::FloatArray(self.value.clone())
         }
     }
 }
@@ -0,0 +1,191 @@
+extern crate pyramid;
+extern crate pyramid_animation;
+extern crate time;
+
+use std::env;
+use std::io;
+use std::io::{Read, Write};
+use std::fs::File;
+use std::path::Path;
+use std::process;
+use time::*;
+use pyramid::pon::*;
+use pyramid_animation::*;
+use pyramid_animation::gltf::*;
+use pyramid_animation::bvh::*;
+use pyramid_animation::binary_clip::*;
+use pyramid_animation::csv::*;
+use pyramid_animation::svg::*;
+
+const USAGE: &'static str = "Usage:
+    animtool info <file> [<clip>]
+    animtool sample <file> [<clip>] --at <seconds>[,<seconds>...]
+    animtool sample <file> [<clip>] --rate <hz> [--until <seconds>]
+    animtool svg <file> [<clip>] [--until <seconds>] [--out <directory>]
+
+Files are read based on their extension: .gltf/.glb, .bvh, .csv, .clip (binary clips),
+anything else is read as a PON animation library.";
+
+fn fail(message: String) -> ! {
+    let _ = writeln!(io::stderr(), "{}", message);
+    process::exit(1)
+}
+
+fn read_file(path: &Path) -> Vec<u8> {
+    let mut bytes = vec![];
+    match File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)) {
+        Ok(_) => bytes,
+        Err(err) => fail(format!("{}: {}", path.display(), err))
+    }
+}
+
+fn read_text(path: &Path) -> String {
+    match String::from_utf8(read_file(path)) {
+        Ok(text) => text,
+        Err(_) => fail(format!("{}: not a text file", path.display()))
+    }
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or("clip".to_string())
+}
+
+fn load_clips(path: &Path) -> Vec<(String, TrackSet)> {
+    let extension = path.extension().map(|ext| ext.to_string_lossy().into_owned()).unwrap_or("".to_string());
+    let options = ImportOptions::new();
+    let res = match extension.as_str() {
+        "gltf" | "glb" => parse_gltf(&read_file(path), path.parent().unwrap_or(Path::new(".")), &options).map_err(|err| err.to_string()),
+        "bvh" => parse_bvh(&read_text(path), &options).map(|clip| vec![(file_stem(path), clip)]).map_err(|err| err.to_string()),
+        "csv" => read_csv(&read_text(path)).map(|clip| vec![(file_stem(path), clip)]).map_err(|err| err.to_string()),
+        "clip" => read_clip(&mut &read_file(path)[..]).map(|clip| vec![(file_stem(path), clip)]).map_err(|err| err.to_string()),
+        _ => parse_animation_library(&read_text(path), path, &mut TranslateContext::empty()).map_err(|err| err.to_string())
+    };
+    match res {
+        Ok(clips) => clips,
+        Err(err) => fail(format!("{}: {}", path.display(), err))
+    }
+}
+
+fn seconds(duration: Duration) -> f32 {
+    duration.num_milliseconds() as f32 / 1000.0
+}
+
+fn property_name(property: &NamedPropRef) -> String {
+    Pon::Reference(property.clone()).to_string()
+}
+
+fn print_info(name: &str, clip: &TrackSet) {
+    println!("{}", name);
+    println!("  duration: {}s", seconds(clip.duration()));
+    println!("  properties:");
+    for property in clip.properties() {
+        println!("    {}", property_name(&property));
+    }
+    println!("  tracks:");
+    for track in &clip.tracks {
+        println!("    {}", track.to_pon().to_string());
+    }
+}
+
+fn print_table(rows: Vec<Vec<String>>) {
+    let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns).map(|c| rows.iter().filter_map(|row| row.get(c)).map(|cell| cell.len()).max().unwrap_or(0)).collect();
+    for row in rows {
+        let cells: Vec<String> = row.iter().enumerate().map(|(c, cell)| format!("{:1$}", cell, widths[c])).collect();
+        println!("{}", cells.join("  ").trim_right());
+    }
+}
+
+fn sample(clip: &TrackSet, times: Vec<f32>) {
+    let properties = clip.properties();
+    let mut rows = vec![];
+    let mut header = vec!["time".to_string()];
+    header.extend(properties.iter().map(property_name));
+    rows.push(header);
+    for time in times {
+        let values = clip.value_at(Duration::milliseconds((time * 1000.0).round() as i64));
+        let mut row = vec![format!("{}", time)];
+        for property in &properties {
+            row.push(match values.iter().find(|&&(ref p, _)| p == property) {
+                Some(&(_, ref value)) => value.value.iter().map(|v| format!("{}", v)).collect::<Vec<String>>().join(" "),
+                None => "-".to_string()
+            });
+        }
+        rows.push(row);
+    }
+    print_table(rows);
+}
+
+fn parse_f32(arg: &str) -> f32 {
+    match arg.trim().parse::<f32>() {
+        Ok(value) => value,
+        Err(_) => fail(format!("{} is not a number", arg))
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 {
+        fail(USAGE.to_string());
+    }
+    let command = args[0].clone();
+    let clips = load_clips(Path::new(&args[1]));
+    let clip_name = match args.get(2) {
+        Some(arg) if !arg.starts_with("--") => Some(arg.clone()),
+        _ => None
+    };
+    let clips: Vec<(String, TrackSet)> = match clip_name {
+        Some(name) => {
+            let selected: Vec<(String, TrackSet)> = clips.into_iter().filter(|clip| clip.0 == name).collect();
+            if selected.len() == 0 {
+                fail(format!("no clip named {}", name));
+            }
+            selected
+        },
+        None => clips
+    };
+    let option = |name: &str| args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1)).map(|arg| arg.clone());
+    let rate = option("--rate").map(|rate| {
+        let rate = parse_f32(&rate);
+        if !(rate > 0.0) {
+            fail(format!("--rate must be positive, found {}", rate));
+        }
+        rate
+    });
+
+    match command.as_str() {
+        "info" => {
+            for (name, clip) in clips {
+                print_info(&name, &clip);
+            }
+        },
+        "sample" => {
+            for (name, clip) in clips {
+                let times: Vec<f32> = match (option("--at"), rate) {
+                    (Some(at), _) => at.split(',').map(parse_f32).collect(),
+                    (None, Some(rate)) => {
+                        let until = option("--until").map(|until| parse_f32(&until)).unwrap_or(seconds(clip.duration()));
+                        let count = (until * rate).ceil() as usize + 1;
+                        (0..count).map(|i| (i as f32 / rate).min(until)).collect()
+                    },
+                    (None, None) => fail(USAGE.to_string())
+                };
+                println!("{}", name);
+                sample(&clip, times);
+            }
+        },
+        "svg" => {
+            let out = option("--out").unwrap_or(".".to_string());
+            for (name, clip) in clips {
+                let until = option("--until").map(|until| parse_f32(&until)).unwrap_or(seconds(clip.duration()));
+                let svg = track_to_svg(&clip, Duration::milliseconds((until * 1000.0) as i64), &PlotOptions::new());
+                let path = Path::new(&out).join(format!("{}.svg", name));
+                match File::create(&path).and_then(|mut file| file.write_all(svg.as_bytes())) {
+                    Ok(_) => println!("{}", path.display()),
+                    Err(err) => fail(format!("{}: {}", path.display(), err))
+                }
+            }
+        },
+        _ => fail(USAGE.to_string())
+    }
+}
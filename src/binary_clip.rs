@@ -0,0 +1,294 @@
+
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::fs::File;
+use std::mem;
+use std::path::Path;
+use time::*;
+use pyramid::pon::*;
+use pyramid::document::*;
+use animatable::*;
+use curve::*;
+use curve_track::*;
+use track::*;
+use track_set::*;
+use loader::*;
+pub use quantized_curve::Quantization;
+
+const MAGIC: &'static [u8] = b"PYAC";
+pub const CLIP_FORMAT_VERSION: u16 = 1;
+
+fn quantization_tag(quantization: Quantization) -> u8 {
+    match quantization {
+        Quantization::Full => 0,
+        Quantization::Bits16 => 1,
+        Quantization::Bits8 => 2
+    }
+}
+
+#[derive(Debug)]
+pub enum ClipError {
+    Io(io::Error),
+    NotAClip,
+    UnsupportedVersion { found: u16, supported: u16 },
+    Invalid(String)
+}
+
+impl fmt::Display for ClipError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ClipError::Io(ref err) => write!(f, "{}", err),
+            &ClipError::NotAClip => write!(f, "not an animation clip"),
+            &ClipError::UnsupportedVersion { found, supported } =>
+                write!(f, "clip format version {} is not supported, expected version {}", found, supported),
+            &ClipError::Invalid(ref err) => write!(f, "invalid clip: {}", err)
+        }
+    }
+}
+
+fn write_u8<W: Write>(writer: &mut W, value: u8) -> io::Result<()> {
+    writer.write_all(&[value])
+}
+fn write_u16<W: Write>(writer: &mut W, value: u16) -> io::Result<()> {
+    writer.write_all(&[value as u8, (value >> 8) as u8])
+}
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8])
+}
+fn write_f32<W: Write>(writer: &mut W, value: f32) -> io::Result<()> {
+    write_u32(writer, unsafe { mem::transmute::<f32, u32>(value) })
+}
+
+struct ClipReader<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+impl<'a> ClipReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ClipError> {
+        if self.pos + n > self.bytes.len() {
+            return Err(ClipError::Invalid("unexpected end of clip".to_string()));
+        }
+        let res = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(res)
+    }
+    fn u8(&mut self) -> Result<u8, ClipError> {
+        Ok(try!(self.take(1))[0])
+    }
+    fn u16(&mut self) -> Result<u16, ClipError> {
+        let b = try!(self.take(2));
+        Ok((b[0] as u16) | ((b[1] as u16) << 8))
+    }
+    fn u32(&mut self) -> Result<u32, ClipError> {
+        let b = try!(self.take(4));
+        Ok((b[0] as u32) | ((b[1] as u32) << 8) | ((b[2] as u32) << 16) | ((b[3] as u32) << 24))
+    }
+    fn f32(&mut self) -> Result<f32, ClipError> {
+        let bits = try!(self.u32());
+        Ok(unsafe { mem::transmute::<u32, f32>(bits) })
+    }
+}
+
+fn loop_tag(loop_type: &Loop) -> u8 {
+    match loop_type {
+        &Loop::Once => 0,
+        &Loop::Forever => 1
+    }
+}
+
+fn write_keys<W: Write>(writer: &mut W, keys: &Vec<Key<Animatable>>, components: usize, quantization: Quantization) -> io::Result<()> {
+    for key in keys {
+        try!(write_f32(writer, key.0));
+    }
+    if quantization == Quantization::Full {
+        for key in keys {
+            for c in 0..components {
+                try!(write_f32(writer, key.1.value[c]));
+            }
+        }
+        return Ok(());
+    }
+    let mut ranges = vec![];
+    for c in 0..components {
+        let min = keys.iter().map(|key| key.1.value[c]).fold(::std::f32::INFINITY, f32::min);
+        let max = keys.iter().map(|key| key.1.value[c]).fold(::std::f32::NEG_INFINITY, f32::max);
+        try!(write_f32(writer, min));
+        try!(write_f32(writer, max));
+        ranges.push((min, max));
+    }
+    let steps = quantization.steps();
+    for key in keys {
+        for c in 0..components {
+            let (min, max) = ranges[c];
+            let q = if max > min { ((key.1.value[c] - min) / (max - min) * steps).round() } else { 0.0 };
+            match quantization {
+                Quantization::Bits16 => try!(write_u16(writer, q as u16)),
+                _ => try!(write_u8(writer, q as u8))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Bakes a track into a binary clip by sampling it `sample_rate` times per second over
+/// `duration`. Every driven property becomes a linearly interpolated key framed track when
+/// the clip is read back.
+pub fn write_clip<W: Write>(writer: &mut W, track: &Track, duration: Duration, sample_rate: f32, loop_type: Loop, quantization: Quantization) -> io::Result<()> {
+    let sampled = sample_track(track, duration, sample_rate);
+    try!(writer.write_all(MAGIC));
+    try!(write_u16(writer, CLIP_FORMAT_VERSION));
+    try!(write_u8(writer, loop_tag(&loop_type)));
+    try!(write_f32(writer, duration.num_milliseconds() as f32 / 1000.0));
+    try!(write_u32(writer, sampled.len() as u32));
+    for &(ref property, _) in &sampled {
+        let name = Pon::Reference(property.clone()).to_string();
+        try!(write_u32(writer, name.len() as u32));
+        try!(writer.write_all(name.as_bytes()));
+    }
+    for &(_, ref keys) in &sampled {
+        // Properties can change size between samples; only the components every key has are kept
+        let components = keys.iter().map(|key| key.1.value.len()).min().unwrap_or(0);
+        try!(write_u32(writer, components as u32));
+        try!(write_u32(writer, keys.len() as u32));
+        try!(write_u8(writer, quantization_tag(quantization)));
+        try!(write_keys(writer, keys, components, quantization));
+    }
+    Ok(())
+}
+
+fn read_keys(reader: &mut ClipReader, components: usize, count: usize, quantization: u8) -> Result<Vec<Key<Animatable>>, ClipError> {
+    let mut times = vec![];
+    for _ in 0..count {
+        times.push(try!(reader.f32()));
+    }
+    let mut values = vec![];
+    match quantization {
+        0 => {
+            for _ in 0..count * components {
+                values.push(try!(reader.f32()));
+            }
+        },
+        1 | 2 => {
+            let mut ranges = vec![];
+            for _ in 0..components {
+                let min = try!(reader.f32());
+                let max = try!(reader.f32());
+                ranges.push((min, max));
+            }
+            let steps = if quantization == 1 { Quantization::Bits16.steps() } else { Quantization::Bits8.steps() };
+            for i in 0..count * components {
+                let q = if quantization == 1 { try!(reader.u16()) as f32 } else { try!(reader.u8()) as f32 };
+                let (min, max) = ranges[i % components];
+                values.push(min + q / steps * (max - min));
+            }
+        },
+        q @ _ => return Err(ClipError::Invalid(format!("unknown quantization {}", q)))
+    }
+    Ok((0..count).map(|i| Key(times[i], Animatable::new(values[i * components..(i + 1) * components].to_vec()))).collect())
+}
+
+/// Reads a binary clip into a track set of key framed tracks, one per property.
+pub fn read_clip<R: Read>(reader: &mut R) -> Result<TrackSet, ClipError> {
+    let mut bytes = vec![];
+    match reader.read_to_end(&mut bytes) {
+        Ok(_) => {},
+        Err(err) => return Err(ClipError::Io(err))
+    };
+    let mut reader = ClipReader { bytes: &bytes, pos: 0 };
+    if bytes.len() < MAGIC.len() || try!(reader.take(MAGIC.len())) != MAGIC {
+        return Err(ClipError::NotAClip);
+    }
+    let version = try!(reader.u16());
+    if version != CLIP_FORMAT_VERSION {
+        return Err(ClipError::UnsupportedVersion { found: version, supported: CLIP_FORMAT_VERSION });
+    }
+    let loop_type = match try!(reader.u8()) {
+        0 => Loop::Once,
+        1 => Loop::Forever,
+        l @ _ => return Err(ClipError::Invalid(format!("unknown loop type {}", l)))
+    };
+    let duration = Duration::milliseconds((try!(reader.f32()) * 1000.0) as i64);
+    let property_count = try!(reader.u32()) as usize;
+    let mut properties = vec![];
+    for _ in 0..property_count {
+        let len = try!(reader.u32()) as usize;
+        let name = match String::from_utf8(try!(reader.take(len)).to_vec()) {
+            Ok(name) => name,
+            Err(_) => return Err(ClipError::Invalid("property name is not utf-8".to_string()))
+        };
+        let property = match Pon::from_string(&name) {
+            Ok(pon) => match pon.as_reference() {
+                Ok(property) => property.clone(),
+                Err(_) => return Err(ClipError::Invalid(format!("{} is not a property reference", name)))
+            },
+            Err(_) => return Err(ClipError::Invalid(format!("{} is not a property reference", name)))
+        };
+        properties.push(property);
+    }
+    let mut tracks: Vec<Box<Track>> = vec![];
+    for property in properties {
+        let components = try!(reader.u32()) as usize;
+        let count = try!(reader.u32()) as usize;
+        let quantization = try!(reader.u8());
+        let keys = try!(read_keys(&mut reader, components, count, quantization));
+        if keys.len() == 0 { continue; }
+        let curve = match LinearKeyFrameCurve::new(keys) {
+            Ok(curve) => curve,
+            Err(err) => return Err(ClipError::Invalid(err.to_string()))
+        };
+        tracks.push(Box::new(CurveTrack::new(property, Box::new(curve), duration, loop_type.clone())));
+    }
+    Ok(TrackSet { tracks: tracks })
+}
+
+/// Reads a binary clip from disk and registers it as a resource, for use with
+/// `track_set_from_resource`.
+pub fn load_clip(document: &mut Document, path: &Path, resource_id: &str) -> Result<(), ClipError> {
+    let track_set = match File::open(path) {
+        Ok(mut file) => try!(read_clip(&mut file)),
+        Err(err) => return Err(ClipError::Io(err))
+    };
+    register_track_set(document, resource_id, track_set);
+    Ok(())
+}
+
+
+#[cfg(test)]
+fn test_clip_track() -> TrackSet {
+    Pon::from_string(
+        "track_set [ key_framed { property: this.x, keys: [[0.0, 0.0], [1.0, 10.0]] }, key_framed { property: this.y, keys: [[0.0, [1.0, 2.0]], [1.0, [3.0, 4.0]]] } ]")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap()
+}
+
+#[test]
+fn test_clip_roundtrip() {
+    let mut bytes = vec![];
+    write_clip(&mut bytes, &test_clip_track(), Duration::seconds(1), 4.0, Loop::Once, Quantization::Full).unwrap();
+    let clip = read_clip(&mut &bytes[..]).unwrap();
+    assert_eq!(clip.value_at(Duration::milliseconds(500)), vec![
+        (NamedPropRef::new(EntityPath::This, "x"), Animatable::new_float(5.0)),
+        (NamedPropRef::new(EntityPath::This, "y"), Animatable::new(vec![2.0, 3.0]))
+    ]);
+}
+
+#[test]
+fn test_clip_quantized() {
+    let mut bytes = vec![];
+    write_clip(&mut bytes, &test_clip_track(), Duration::seconds(1), 4.0, Loop::Once, Quantization::Bits16).unwrap();
+    let clip = read_clip(&mut &bytes[..]).unwrap();
+    let values = clip.value_at(Duration::milliseconds(250));
+    assert!((values[0].1.value[0] - 2.5).abs() < 0.001);
+}
+
+#[test]
+fn test_clip_version_mismatch() {
+    let mut bytes = vec![];
+    write_clip(&mut bytes, &test_clip_track(), Duration::seconds(1), 4.0, Loop::Once, Quantization::Full).unwrap();
+    bytes[4] = 99;
+    match read_clip(&mut &bytes[..]) {
+        Err(ClipError::UnsupportedVersion { found: 99, supported: CLIP_FORMAT_VERSION }) => {},
+        r @ _ => panic!("expected a version error, got {:?}", r)
+    }
+}
@@ -0,0 +1,345 @@
+
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::fs::File;
+use std::path::Path;
+use time::*;
+use pyramid::pon::*;
+use pyramid::document::*;
+use animatable::*;
+use curve::*;
+use curve_track::*;
+use track::*;
+use track_set::*;
+use import::*;
+use loader::*;
+
+#[derive(Debug)]
+pub enum BvhError {
+    Io(io::Error),
+    /// A parse error and the line it occurred on
+    Parse(usize, String)
+}
+
+impl fmt::Display for BvhError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &BvhError::Io(ref err) => write!(f, "{}", err),
+            &BvhError::Parse(line, ref err) => write!(f, "line {}: {}", line, err)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Channel {
+    Position(usize),
+    Rotation(usize)
+}
+
+#[derive(Debug)]
+struct Joint {
+    name: String,
+    offset: [f32; 3],
+    channels: Vec<Channel>
+}
+
+struct Tokens<'a> {
+    tokens: Vec<(usize, &'a str)>,
+    pos: usize
+}
+
+impl<'a> Tokens<'a> {
+    fn new(source: &'a str) -> Tokens<'a> {
+        let mut tokens = vec![];
+        for (i, line) in source.lines().enumerate() {
+            for word in line.split_whitespace() {
+                tokens.push((i + 1, word));
+            }
+        }
+        Tokens { tokens: tokens, pos: 0 }
+    }
+    fn line(&self) -> usize {
+        match self.tokens.get(self.pos) {
+            Some(&(line, _)) => line,
+            None => self.tokens.last().map(|&(line, _)| line).unwrap_or(0)
+        }
+    }
+    fn error<T>(&self, message: String) -> Result<T, BvhError> {
+        Err(BvhError::Parse(self.line(), message))
+    }
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|&(_, word)| word)
+    }
+    fn next(&mut self) -> Result<&'a str, BvhError> {
+        match self.peek() {
+            Some(word) => {
+                self.pos += 1;
+                Ok(word)
+            },
+            None => self.error("unexpected end of file".to_string())
+        }
+    }
+    fn expect(&mut self, expected: &str) -> Result<(), BvhError> {
+        let word = try!(self.next());
+        if word != expected {
+            self.pos -= 1;
+            return self.error(format!("expected {}, found {}", expected, word));
+        }
+        Ok(())
+    }
+    fn next_f32(&mut self) -> Result<f32, BvhError> {
+        let word = try!(self.next());
+        match word.parse::<f32>() {
+            Ok(value) => Ok(value),
+            Err(_) => {
+                self.pos -= 1;
+                self.error(format!("expected a number, found {}", word))
+            }
+        }
+    }
+    fn next_usize(&mut self) -> Result<usize, BvhError> {
+        let word = try!(self.next());
+        match word.parse::<usize>() {
+            Ok(value) => Ok(value),
+            Err(_) => {
+                self.pos -= 1;
+                self.error(format!("expected a count, found {}", word))
+            }
+        }
+    }
+}
+
+fn parse_channel(tokens: &mut Tokens) -> Result<Channel, BvhError> {
+    let word = try!(tokens.next());
+    match word {
+        "Xposition" => Ok(Channel::Position(0)),
+        "Yposition" => Ok(Channel::Position(1)),
+        "Zposition" => Ok(Channel::Position(2)),
+        "Xrotation" => Ok(Channel::Rotation(0)),
+        "Yrotation" => Ok(Channel::Rotation(1)),
+        "Zrotation" => Ok(Channel::Rotation(2)),
+        _ => {
+            tokens.pos -= 1;
+            tokens.error(format!("unknown channel {}", word))
+        }
+    }
+}
+
+/// Parses a joint and its children, appending them in the order their channels appear in
+/// the motion data.
+fn parse_joint(tokens: &mut Tokens, name: String, joints: &mut Vec<Joint>) -> Result<(), BvhError> {
+    try!(tokens.expect("{"));
+    try!(tokens.expect("OFFSET"));
+    let offset = [try!(tokens.next_f32()), try!(tokens.next_f32()), try!(tokens.next_f32())];
+    let mut channels = vec![];
+    if tokens.peek() == Some("CHANNELS") {
+        try!(tokens.next());
+        for _ in 0..try!(tokens.next_usize()) {
+            channels.push(try!(parse_channel(tokens)));
+        }
+    }
+    joints.push(Joint { name: name, offset: offset, channels: channels });
+    loop {
+        match try!(tokens.next()) {
+            "JOINT" => {
+                let child = try!(tokens.next()).to_string();
+                try!(parse_joint(tokens, child, joints));
+            },
+            "End" => {
+                try!(tokens.expect("Site"));
+                try!(tokens.expect("{"));
+                try!(tokens.expect("OFFSET"));
+                for _ in 0..3 {
+                    try!(tokens.next_f32());
+                }
+                try!(tokens.expect("}"));
+            },
+            "}" => return Ok(()),
+            word @ _ => {
+                tokens.pos -= 1;
+                return tokens.error(format!("expected JOINT, End Site or }}, found {}", word));
+            }
+        }
+    }
+}
+
+fn quaternion_mul(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    [
+        a[3] * b[0] + a[0] * b[3] + a[1] * b[2] - a[2] * b[1],
+        a[3] * b[1] - a[0] * b[2] + a[1] * b[3] + a[2] * b[0],
+        a[3] * b[2] + a[0] * b[1] - a[1] * b[0] + a[2] * b[3],
+        a[3] * b[3] - a[0] * b[0] - a[1] * b[1] - a[2] * b[2]
+    ]
+}
+
+fn axis_rotation(axis: usize, degrees: f32) -> [f32; 4] {
+    let half = degrees.to_radians() / 2.0;
+    let mut q = [0.0, 0.0, 0.0, half.cos()];
+    q[axis] = half.sin();
+    q
+}
+
+/// Parses a BVH file into a track set with a `translation` track for every joint that has
+/// position channels and a `rotation` track, as an [x, y, z, w] quaternion, for every joint
+/// that has rotation channels. Rotation channels are composed in the order they are listed,
+/// so `Zrotation Xrotation Yrotation` gives Z * X * Y. Rotation keys are kept in the same
+/// hemisphere as the key before them, so angles wrapping around do not make joints flip.
+pub fn parse_bvh(source: &str, options: &ImportOptions) -> Result<TrackSet, BvhError> {
+    let mut tokens = Tokens::new(source);
+    try!(tokens.expect("HIERARCHY"));
+    try!(tokens.expect("ROOT"));
+    let mut joints = vec![];
+    let root = try!(tokens.next()).to_string();
+    try!(parse_joint(&mut tokens, root, &mut joints));
+    try!(tokens.expect("MOTION"));
+    try!(tokens.expect("Frames:"));
+    let frames = try!(tokens.next_usize());
+    try!(tokens.expect("Frame"));
+    try!(tokens.expect("Time:"));
+    let frame_time = try!(tokens.next_f32());
+    if frame_time <= 0.0 {
+        tokens.pos -= 1;
+        return tokens.error(format!("frame time must be positive, found {}", frame_time));
+    }
+    let channels_per_frame = joints.iter().map(|joint| joint.channels.len()).fold(0, |a, b| a + b);
+    let mut data = Vec::with_capacity(frames * channels_per_frame);
+    for _ in 0..frames * channels_per_frame {
+        data.push(try!(tokens.next_f32()));
+    }
+    // A single frame still plays for a frame, so looping tracks have a length to loop over
+    let duration = Duration::milliseconds((frames.saturating_sub(1).max(1) as f32 * frame_time * 1000.0) as i64);
+
+    let mut tracks: Vec<Box<Track>> = vec![];
+    let mut first_channel = 0;
+    for joint in &joints {
+        let has_position = joint.channels.iter().any(|c| match c { &Channel::Position(_) => true, _ => false });
+        let has_rotation = joint.channels.iter().any(|c| match c { &Channel::Rotation(_) => true, _ => false });
+        let mut translation_keys = vec![];
+        let mut rotation_keys: Vec<Key<Animatable>> = vec![];
+        for frame in 0..frames {
+            let start = frame * channels_per_frame + first_channel;
+            let values = &data[start..start + joint.channels.len()];
+            let time = frame as f32 * frame_time;
+            let mut translation = joint.offset;
+            let mut rotation = [0.0, 0.0, 0.0, 1.0];
+            for (channel, value) in joint.channels.iter().zip(values.iter()) {
+                match channel {
+                    &Channel::Position(axis) => translation[axis] = *value,
+                    &Channel::Rotation(axis) => rotation = quaternion_mul(rotation, axis_rotation(axis, *value))
+                }
+            }
+            if has_position {
+                translation_keys.push(Key(time, Animatable::new(translation.to_vec())));
+            }
+            if has_rotation {
+                // q and -q are the same rotation, and keeping consecutive keys in the same
+                // hemisphere makes interpolating between them take the short way around
+                let flip = match rotation_keys.last() {
+                    Some(&Key(_, ref previous)) => (0..4).map(|i| previous.value[i] * rotation[i]).fold(0.0, |s, d| s + d) < 0.0,
+                    None => false
+                };
+                if flip {
+                    for c in rotation.iter_mut() { *c = -*c; }
+                }
+                rotation_keys.push(Key(time, Animatable::new(rotation.to_vec())));
+            }
+        }
+        first_channel += joint.channels.len();
+        for (property_key, keys) in vec![("translation", translation_keys), ("rotation", rotation_keys)] {
+            if keys.len() == 0 { continue; }
+            let curve = match LinearKeyFrameCurve::new(keys) {
+                Ok(curve) => curve,
+                Err(err) => return tokens.error(err.to_string())
+            };
+            let property = NamedPropRef::new((options.entity_path)(&joint.name), property_key);
+            tracks.push(Box::new(CurveTrack::new(property, Box::new(curve), duration, options.loop_type.clone())));
+        }
+    }
+    Ok(TrackSet { tracks: tracks })
+}
+
+/// Imports a BVH file and registers it as a resource named `options.resource_prefix`
+/// followed by the file name without extension. Returns the resource name.
+pub fn load_bvh(document: &mut Document, path: &Path, options: &ImportOptions) -> Result<String, BvhError> {
+    let mut source = String::new();
+    match File::open(path).and_then(|mut file| file.read_to_string(&mut source)) {
+        Ok(_) => {},
+        Err(err) => return Err(BvhError::Io(err))
+    };
+    let track_set = try!(parse_bvh(&source, options));
+    let name = match path.file_stem() {
+        Some(stem) => stem.to_string_lossy().into_owned(),
+        None => path.to_string_lossy().into_owned()
+    };
+    let resource_id = format!("{}{}", options.resource_prefix, name);
+    register_track_set(document, &resource_id, track_set);
+    Ok(resource_id)
+}
+
+
+#[cfg(test)]
+const TEST_BVH: &'static str = "HIERARCHY
+ROOT hips
+{
+    OFFSET 0.0 1.0 0.0
+    CHANNELS 6 Xposition Yposition Zposition Zrotation Xrotation Yrotation
+    JOINT arm
+    {
+        OFFSET 1.0 0.0 0.0
+        CHANNELS 3 Zrotation Xrotation Yrotation
+        End Site
+        {
+            OFFSET 1.0 0.0 0.0
+        }
+    }
+}
+MOTION
+Frames: 2
+Frame Time: 0.5
+0.0 1.0 0.0 0.0 0.0 0.0 0.0 0.0 0.0
+2.0 1.0 0.0 0.0 0.0 0.0 90.0 0.0 0.0
+";
+
+#[test]
+fn test_bvh_tracks() {
+    let track_set = parse_bvh(TEST_BVH, &ImportOptions::new()).unwrap();
+    assert_eq!(track_set.tracks.len(), 3);
+    let values = track_set.value_at(Duration::milliseconds(250));
+    assert_eq!(values[0], (NamedPropRef::new(EntityPath::Named("hips".to_string()), "translation"), Animatable::new(vec![1.0, 1.0, 0.0])));
+    let values = track_set.value_at(Duration::milliseconds(500));
+    let (ref property, ref rotation) = values[2];
+    assert_eq!(property, &NamedPropRef::new(EntityPath::Named("arm".to_string()), "rotation"));
+    let expected = [0.0, 0.0, (45.0f32).to_radians().sin(), (45.0f32).to_radians().cos()];
+    for i in 0..4 {
+        assert!((rotation.value[i] - expected[i]).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn test_bvh_parse_error_line() {
+    let err = parse_bvh("HIERARCHY\nROOT hips\n{\n    OFFSET 0.0 oops 0.0\n}", &ImportOptions::new()).unwrap_err();
+    assert_eq!(err.to_string(), "line 4: expected a number, found oops".to_string());
+}
+
+#[test]
+fn test_bvh_rotation_continuity() {
+    let source = "HIERARCHY\nROOT hips\n{\n    OFFSET 0.0 0.0 0.0\n    CHANNELS 1 Zrotation\n}\nMOTION\nFrames: 2\nFrame Time: 1.0\n170.0\n-170.0\n";
+    let track_set = parse_bvh(source, &ImportOptions::new()).unwrap();
+    let (_, ref rotation) = track_set.value_at(Duration::milliseconds(500))[0];
+    // Halfway from 170 to -170 degrees is 180 degrees, not 0
+    assert!(rotation.value[2].abs() > 0.99);
+    assert!(rotation.value[3].abs() < 0.01);
+}
+
+#[test]
+fn test_bvh_single_frame() {
+    let source = "HIERARCHY\nROOT hips\n{\n    OFFSET 0.0 0.0 0.0\n    CHANNELS 1 Xposition\n}\nMOTION\nFrames: 1\nFrame Time: 0.5\n2.0\n";
+    let mut options = ImportOptions::new();
+    options.loop_type = Loop::Forever;
+    let track_set = parse_bvh(source, &options).unwrap();
+    assert_eq!(track_set.duration(), Duration::milliseconds(500));
+    assert_eq!(track_set.value_at(Duration::seconds(3))[0].1, Animatable::new(vec![2.0, 0.0, 0.0]));
+    let err = parse_bvh(&source.replace("0.5", "0.0"), &ImportOptions::new()).unwrap_err();
+    assert_eq!(err.to_string(), "line 9: frame time must be positive, found 0".to_string());
+}
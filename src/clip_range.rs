@@ -0,0 +1,200 @@
+
+use std::cmp::Ordering;
+use time::*;
+use pyramid::pon::*;
+use pyramid::document::*;
+use animatable::*;
+use curve::*;
+use curve_track::*;
+use track::*;
+use validate::*;
+use track_set::*;
+
+/// Plays only the part of a track between `start` and `end`, as if it started at zero, e.g.
+/// one move out of a motion capture take with several.
+#[derive(Debug)]
+pub struct ClipRangeTrack {
+    pub track: Box<Track>,
+    pub start: Duration,
+    pub end: Duration,
+    pub loop_type: Loop
+}
+
+impl ClipRangeTrack {
+    /// The child's time at `time`, or None once a range that does not loop has ended
+    fn child_time(&self, time: Duration) -> Option<Duration> {
+        let length = self.end - self.start;
+        let time = if time < Duration::zero() { Duration::zero() } else { time };
+        if time > length {
+            if self.loop_type == Loop::Forever && length > Duration::zero() {
+                Some(self.start + Duration::milliseconds(time.num_milliseconds() % length.num_milliseconds()))
+            } else {
+                None
+            }
+        } else {
+            Some(self.start + time)
+        }
+    }
+}
+
+impl Track for ClipRangeTrack {
+    fn value_at(&self, time: Duration) -> Vec<(NamedPropRef, Animatable)> {
+        self.value_at_with(time, &NoProperties)
+    }
+    fn value_at_with(&self, time: Duration, properties: &PropertySource) -> Vec<(NamedPropRef, Animatable)> {
+        match self.child_time(time) {
+            Some(time) => self.track.value_at_with(time, properties),
+            None => vec![]
+        }
+    }
+    /// The delta is passed on as it is, also when the range loops back to its start, since
+    /// stateful tracks advance by the time that has passed rather than by their own time.
+    fn advance(&mut self, time: Duration, delta: Duration, properties: &PropertySource) {
+        if let Some(time) = self.child_time(time) {
+            self.track.advance(time, delta, properties);
+        }
+    }
+    fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+    fn properties(&self) -> Vec<NamedPropRef> {
+        self.track.properties()
+    }
+    /// The child's keys inside the range, moved to where the range starts at zero
+    fn key_times(&self) -> Vec<f32> {
+        let (start, end) = (seconds(self.start), seconds(self.end));
+        self.track.key_times().into_iter().filter(|&t| t >= start && t <= end).map(|t| t - start).collect()
+    }
+    fn refresh_resources(&mut self, document: &Document) -> Vec<Diagnostic> {
+        self.track.refresh_resources(document)
+    }
+    fn is_stateful(&self) -> bool {
+        self.track.is_stateful()
+    }
+}
+
+fn seconds(time: Duration) -> f32 {
+    time.num_milliseconds() as f32 / 1000.0
+}
+
+impl ToPon for ClipRangeTrack {
+    fn to_pon(&self) -> Pon {
+        typed_pon("clip_range", object_pon(vec![
+            ("track", self.track.to_pon()),
+            ("start", seconds(self.start).to_pon()),
+            ("end", seconds(self.end).to_pon()),
+            ("loop", self.loop_type.to_pon())
+        ]))
+    }
+}
+
+impl Translatable<ClipRangeTrack> for Pon {
+    fn inner_translate(&self, context: &mut TranslateContext) -> Result<ClipRangeTrack, PonTranslateErr> {
+        self.as_typed(|&TypedPon { ref data, .. }| -> Result<ClipRangeTrack, PonTranslateErr> {
+            let track = try!(data.field_as::<Box<Track>>("track", context));
+            let start: f32 = try!(data.field_as_or("start", 0.0, context));
+            let end: f32 = try!(data.field_as_or("end", seconds(track.duration()), context));
+            if end < start {
+                return Err(PonTranslateErr::InvalidValue { value: format!("clip range ends at {} before it starts at {}", end, start) });
+            }
+            Ok(ClipRangeTrack {
+                track: track,
+                start: Duration::milliseconds((start * 1000.0) as i64),
+                end: Duration::milliseconds((end * 1000.0) as i64),
+                loop_type: try!(data.field_as_or("loop", Loop::Once, context))
+            })
+        })
+    }
+}
+
+/// Samples the range of a track into keys rebased to zero, at `sample_rate` and at `key_times`
+/// given in the track's time.
+fn bake_range(track: &Track, start: Duration, end: Duration, key_times: Vec<f32>, sample_rate: f32) -> Vec<(NamedPropRef, Vec<Key<Animatable>>)> {
+    let length = seconds(end - start);
+    let samples = SampleTimes::new(0.0, length, sample_rate);
+    let mut times: Vec<f32> = (0..samples.count()).map(|i| samples.time(i)).collect();
+    times.extend(key_times.into_iter().map(|t| t - seconds(start)).filter(|&t| t > 0.0 && t < length));
+    times.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    times.dedup();
+    let mut res: Vec<(NamedPropRef, Vec<Key<Animatable>>)> = vec![];
+    for time in times {
+        for (property, value) in track.value_at(start + Duration::milliseconds((time * 1000.0).round() as i64)) {
+            match res.iter().position(|&(ref p, _)| p == &property) {
+                Some(index) => res[index].1.push(Key(time, value)),
+                None => res.push((property, vec![Key(time, value)]))
+            }
+        }
+    }
+    res
+}
+
+fn baked_track(property: NamedPropRef, keys: Vec<Key<Animatable>>, length: Duration) -> CurveTrack {
+    let curve = LinearKeyFrameCurve::new(keys).expect("baked keys are sorted and never empty");
+    CurveTrack::new(property, Box::new(curve), length, Loop::Once)
+}
+
+/// Cuts the range between `start` and `end` out of a curve track into a linear key framed track
+/// starting at zero. Keys within the range are kept where they are, and the curve is sampled
+/// `sample_rate` times per second in between, so only linear curves are copied exactly.
+/// Returns None if the track has no values in the range.
+pub fn trim_curve_track(track: &CurveTrack, start: Duration, end: Duration, sample_rate: f32) -> Option<CurveTrack> {
+    bake_range(track, start, end, track.key_times(), sample_rate).into_iter().next()
+        .map(|(property, keys)| baked_track(property, keys, end - start))
+}
+
+/// Cuts the range between `start` and `end` out of every track of a track set into linear key
+/// framed tracks starting at zero, keeping the keys of each track like `trim_curve_track`.
+pub fn trim_track_set(track_set: &TrackSet, start: Duration, end: Duration, sample_rate: f32) -> TrackSet {
+    let mut tracks: Vec<Box<Track>> = vec![];
+    for track in &track_set.tracks {
+        for (property, keys) in bake_range(&**track, start, end, track.key_times(), sample_rate) {
+            tracks.push(Box::new(baked_track(property, keys, end - start)));
+        }
+    }
+    TrackSet { tracks: tracks }
+}
+
+
+#[test]
+fn test_clip_range() {
+    let track: ClipRangeTrack = Pon::from_string(
+        "clip_range { track: key_framed { property: this.x, keys: [[0.0, 0.0], [4.0, 4.0]], duration: 4.0 }, start: 1.0, end: 3.0, loop: 'forever' }")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    let x = NamedPropRef::new(EntityPath::This, "x");
+    assert_eq!(track.duration(), Duration::seconds(2));
+    assert_eq!(track.value_at(Duration::zero()), vec![(x.clone(), Animatable::new_float(1.0))]);
+    assert_eq!(track.value_at(Duration::milliseconds(2500)), vec![(x.clone(), Animatable::new_float(1.5))]);
+    let roundtrip: ClipRangeTrack = track.to_pon().translate(&mut TranslateContext::empty()).unwrap();
+    assert_eq!(roundtrip.to_pon(), track.to_pon());
+}
+
+#[test]
+fn test_clip_range_key_times() {
+    let track: ClipRangeTrack = Pon::from_string(
+        "clip_range { track: key_framed { property: this.x, keys: [[0.0, 0.0], [1.5, 1.0], [2.0, 0.0], [4.0, 1.0]], duration: 4.0 }, start: 1.0, end: 3.0 }")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    assert_eq!(track.key_times(), vec![0.5, 1.0]);
+}
+
+#[test]
+fn test_trim_curve_track() {
+    let track: CurveTrack = Pon::from_string(
+        "key_framed { property: this.x, keys: [[0.0, 0.0], [1.25, 5.0], [4.0, 0.0]], duration: 4.0 }")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    let trimmed = trim_curve_track(&track, Duration::seconds(1), Duration::seconds(2), 2.0).unwrap();
+    assert_eq!(trimmed.curve.key_times(), vec![0.0, 0.25, 0.5, 1.0]);
+    assert_eq!(trimmed.duration(), Duration::seconds(1));
+    for &ms in &[0, 250, 500, 1000] {
+        assert_eq!(trimmed.value_at(Duration::milliseconds(ms)), track.value_at(Duration::milliseconds(1000 + ms)));
+    }
+}
+
+#[test]
+fn test_trim_track_set() {
+    let track_set: TrackSet = Pon::from_string(
+        "track_set [ key_framed { property: this.x, keys: [[0.0, 0.0], [1.25, 5.0], [4.0, 0.0]], duration: 4.0 } ]")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    let trimmed = trim_track_set(&track_set, Duration::seconds(1), Duration::seconds(2), 2.0);
+    assert_eq!(trimmed.key_times(), vec![0.0, 0.25, 0.5, 1.0]);
+    assert_eq!(trimmed.value_at(Duration::milliseconds(250)), track_set.value_at(Duration::milliseconds(1250)));
+}
@@ -0,0 +1,186 @@
+
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::cmp::Ordering;
+use time::*;
+use pyramid::pon::*;
+use animatable::*;
+use curve::*;
+use curve_track::*;
+use track::*;
+use track_set::*;
+
+#[derive(Debug, PartialEq)]
+pub struct CsvError {
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+fn cells(line: &str) -> Vec<&str> {
+    line.split(',').map(|cell| cell.trim().trim_matches('"')).collect()
+}
+
+/// Splits a column name such as `this.position[1]` into the property and component index.
+fn parse_column(name: &str, line: usize) -> Result<(NamedPropRef, usize), CsvError> {
+    let (property, component) = match (name.rfind('['), name.ends_with("]")) {
+        (Some(open), true) => match name[open + 1..name.len() - 1].parse::<usize>() {
+            Ok(component) => (&name[..open], component),
+            Err(_) => return Err(CsvError { line: line, message: format!("invalid component in column {}", name) })
+        },
+        _ => (name, 0)
+    };
+    match Pon::from_string(property).ok().and_then(|pon| pon.as_reference().ok().map(|p| p.clone())) {
+        Some(property) => Ok((property, component)),
+        None => Err(CsvError { line: line, message: format!("column {} is not a property reference", name) })
+    }
+}
+
+/// Reads a CSV file with a time column in seconds followed by one column per property into a
+/// track set of key framed tracks. Properties with several components use one column per
+/// component, suffixed with the component index, e.g. `this.position[0]`. Rows where a
+/// property has empty cells get no key for that property.
+pub fn read_csv(source: &str) -> Result<TrackSet, CsvError> {
+    let mut lines = source.lines().enumerate().filter(|&(_, line)| line.trim().len() > 0);
+    let header = match lines.next() {
+        Some((_, header)) => cells(header),
+        None => return Err(CsvError { line: 1, message: "missing header".to_string() })
+    };
+    // For each property, the columns holding its components in component order
+    let mut properties: Vec<(NamedPropRef, Vec<(usize, usize)>)> = vec![];
+    for (column, name) in header.iter().enumerate().skip(1) {
+        let (property, component) = try!(parse_column(name, 1));
+        match properties.iter().position(|&(ref p, _)| p == &property) {
+            Some(index) => properties[index].1.push((component, column)),
+            None => properties.push((property, vec![(component, column)]))
+        }
+    }
+    for &mut (ref property, ref mut columns) in properties.iter_mut() {
+        columns.sort_by(|a, b| a.0.cmp(&b.0));
+        if columns.iter().enumerate().any(|(index, &(component, _))| component != index) {
+            let name = Pon::Reference(property.clone()).to_string();
+            return Err(CsvError { line: 1, message: format!("the components of {} must be numbered from 0 without gaps", name) });
+        }
+    }
+    let mut keys: Vec<Vec<Key<Animatable>>> = properties.iter().map(|_| vec![]).collect();
+    let mut last_time = 0.0;
+    for (i, line) in lines {
+        let row = cells(line);
+        let parse = |column: usize| -> Result<Option<f32>, CsvError> {
+            match row.get(column) {
+                Some(cell) if cell.len() > 0 => match cell.parse::<f32>() {
+                    Ok(value) => Ok(Some(value)),
+                    Err(_) => Err(CsvError { line: i + 1, message: format!("{} is not a number", cell) })
+                },
+                _ => Ok(None)
+            }
+        };
+        let time = match try!(parse(0)) {
+            Some(time) => time,
+            None => return Err(CsvError { line: i + 1, message: "missing time".to_string() })
+        };
+        if time < 0.0 {
+            return Err(CsvError { line: i + 1, message: format!("negative time {}", time) });
+        }
+        if time < last_time {
+            return Err(CsvError { line: i + 1, message: "times must be increasing".to_string() });
+        }
+        last_time = time;
+        for (p, &(_, ref columns)) in properties.iter().enumerate() {
+            let mut value = vec![];
+            for &(_, column) in columns {
+                if let Some(v) = try!(parse(column)) {
+                    value.push(v);
+                }
+            }
+            if value.len() == columns.len() {
+                keys[p].push(Key(time, Animatable::new(value)));
+            }
+        }
+    }
+    let mut tracks: Vec<Box<Track>> = vec![];
+    for ((property, _), keys) in properties.into_iter().zip(keys.into_iter()) {
+        if keys.len() == 0 { continue; }
+        let curve = LinearKeyFrameCurve::new(keys).expect("times are checked to be increasing");
+        tracks.push(Box::new(CurveTrack::new(property, Box::new(curve), Duration::milliseconds((last_time * 1000.0) as i64), Loop::Once)));
+    }
+    Ok(TrackSet { tracks: tracks })
+}
+
+/// Samples a track `sample_rate` times per second over `duration` and writes it as CSV, in
+/// the format read by `read_csv`.
+pub fn write_csv<W: Write>(writer: &mut W, track: &Track, duration: Duration, sample_rate: f32) -> io::Result<()> {
+    let sampled = sample_track(track, duration, sample_rate);
+    let mut times: Vec<f32> = sampled.iter().flat_map(|&(_, ref keys)| keys.iter().map(|key| key.0)).collect();
+    times.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    times.dedup();
+    let components: Vec<usize> = sampled.iter().map(|&(_, ref keys)| keys.iter().map(|key| key.1.value.len()).max().unwrap_or(0)).collect();
+
+    let mut header = vec!["time".to_string()];
+    for (&(ref property, _), &n) in sampled.iter().zip(components.iter()) {
+        let name = Pon::Reference(property.clone()).to_string();
+        if n == 1 {
+            header.push(name);
+        } else {
+            for c in 0..n {
+                header.push(format!("{}[{}]", name, c));
+            }
+        }
+    }
+    try!(writeln!(writer, "{}", header.join(",")));
+
+    let mut cursors = vec![0; sampled.len()];
+    for time in times {
+        let mut row = vec![format!("{}", time)];
+        for (p, &(_, ref keys)) in sampled.iter().enumerate() {
+            let key = match keys.get(cursors[p]) {
+                Some(key) if key.0 == time => {
+                    cursors[p] += 1;
+                    Some(key)
+                },
+                _ => None
+            };
+            for c in 0..components[p] {
+                row.push(match key.and_then(|key| key.1.value.get(c)) {
+                    Some(value) => format!("{}", value),
+                    None => "".to_string()
+                });
+            }
+        }
+        try!(writeln!(writer, "{}", row.join(",")));
+    }
+    Ok(())
+}
+
+
+#[test]
+fn test_read_csv() {
+    let track_set = read_csv("time,this.x,this.position[0],this.position[1]\n0,0,1,2\n1,10,3,\n2,20,5,6\n").unwrap();
+    assert_eq!(track_set.value_at(Duration::milliseconds(500)), vec![
+        (NamedPropRef::new(EntityPath::This, "x"), Animatable::new_float(5.0)),
+        (NamedPropRef::new(EntityPath::This, "position"), Animatable::new(vec![2.0, 3.0]))
+    ]);
+}
+
+#[test]
+fn test_read_csv_error() {
+    assert_eq!(read_csv("time,this.x\n0,0\n1,abc\n").unwrap_err(), CsvError { line: 3, message: "abc is not a number".to_string() });
+    assert_eq!(read_csv("time,this.x\n-1,0\n1,1\n").unwrap_err(), CsvError { line: 2, message: "negative time -1".to_string() });
+    assert_eq!(read_csv("time,this.p[0],this.p[2]\n0,0,0\n").unwrap_err(), CsvError { line: 1, message: "the components of this.p must be numbered from 0 without gaps".to_string() });
+}
+
+#[test]
+fn test_write_csv() {
+    let track: CurveTrack = Pon::from_string(
+        "key_framed { property: this.position, keys: [[0.0, [0.0, 1.0]], [1.0, [1.0, 3.0]]] }")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    let mut out = vec![];
+    write_csv(&mut out, &track, Duration::seconds(1), 2.0).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "time,this.position[0],this.position[1]\n0,0,1\n0.5,0.5,2\n1,1,3\n".to_string());
+}
@@ -1,10 +1,43 @@
 #[cfg(test)]
 use cgmath::*;
+use std::fmt;
 use std::fmt::Debug;
+use pyramid::pon::*;
 use animatable::*;
+use track::*;
 
 pub trait Curve<T> : Debug {
     fn value(&self, time: f32) -> T;
+    /// Same as `value`, but curves with keys start looking for the keys around `time` where
+    /// the previous lookup with `cursor` ended.
+    fn value_with_cursor(&self, time: f32, _cursor: &mut KeyCursor) -> T {
+        self.value(time)
+    }
+    /// The times of the curve's keys, for curves that have any
+    fn key_times(&self) -> Vec<f32> { vec![] }
+}
+
+/// The rate of change of a curve, per unit of curve time. The provided methods use finite
+/// differences; curves that can compute their derivatives exactly override them.
+/// Jumps in a curve, like the ones of step curves, are not part of the derivatives.
+pub trait Differentiable : Curve<Animatable> {
+    fn velocity(&self, time: f32) -> Animatable {
+        let h = 0.001;
+        self.value(time + h).add_weighted(-1.0, &self.value(time - h)).weighted(0.5 / h)
+    }
+    fn acceleration(&self, time: f32) -> Animatable {
+        let h = 0.01;
+        self.value(time + h).add_weighted(-2.0, &self.value(time)).add_weighted(1.0, &self.value(time - h)).weighted(1.0 / (h * h))
+    }
+}
+
+/// Curves that can be played by a `CurveTrack`, which needs to be able to write them back to
+/// PON and to tell how fast they change.
+pub trait AnimationCurve : Differentiable + ToPon {}
+impl<C: Differentiable + ToPon> AnimationCurve for C {}
+
+fn zero(value: &Animatable) -> Animatable {
+    value.weighted(0.0)
 }
 
 
@@ -21,71 +54,441 @@ impl<T: Debug + Clone> Curve<T> for FixedValueCurve<T> {
 #[derive(PartialEq, Debug)]
 pub struct Key<T: Clone>(pub f32, pub T);
 
+/// Uniformly spaced sample times. There is always at least one sample.
+#[derive(PartialEq, Debug, Clone)]
+pub struct SampleTimes {
+    start: f32,
+    interval: f32,
+    count: usize
+}
+
+impl SampleTimes {
+    /// Samples from `start` to `end` at least `sample_rate` times per second, including both ends.
+    pub fn new(start: f32, end: f32, sample_rate: f32) -> SampleTimes {
+        let count = ((end - start) * sample_rate).ceil().max(0.0) as usize + 1;
+        SampleTimes::with_count(start, end, count)
+    }
+    /// Takes `count` samples from `start` to `end`, including both ends. A count of zero
+    /// takes one sample at `start`.
+    pub fn with_count(start: f32, end: f32, count: usize) -> SampleTimes {
+        let count = if count < 1 { 1 } else { count };
+        SampleTimes {
+            start: start,
+            interval: if count > 1 { (end - start) / (count - 1) as f32 } else { 0.0 },
+            count: count
+        }
+    }
+    pub fn start(&self) -> f32 {
+        self.start
+    }
+    pub fn interval(&self) -> f32 {
+        self.interval
+    }
+    pub fn count(&self) -> usize {
+        self.count
+    }
+    pub fn time(&self, index: usize) -> f32 {
+        self.start + self.interval * index as f32
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum CurveError {
+    NoKeys,
+    /// The key at `index` has a time before the key preceding it
+    UnsortedKeys { index: usize }
+}
+
+impl fmt::Display for CurveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &CurveError::NoKeys => write!(f, "a key framed curve needs at least one key"),
+            &CurveError::UnsortedKeys { index } => write!(f, "key {} comes before the key preceding it", index)
+        }
+    }
+}
+
+/// Key times need to be in order. Two keys may share a time, which makes the curve jump
+/// from the first key's value to the second one's at that time.
+fn check_key_times<I: Iterator<Item=f32>>(times: I) -> Result<(), CurveError> {
+    let mut previous = None;
+    for (index, time) in times.enumerate() {
+        match previous {
+            Some(previous) if time < previous => return Err(CurveError::UnsortedKeys { index: index }),
+            _ => previous = Some(time)
+        }
+    }
+    match previous {
+        Some(_) => Ok(()),
+        None => Err(CurveError::NoKeys)
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub struct LinearKeyFrameCurve<T: Clone> {
-    pub keys: Vec<Key<T>>
+    keys: Vec<Key<T>>
+}
+
+/// Remembers which keys the previous lookup in a curve landed between. Curves are usually
+/// sampled at steadily increasing times, so the next lookup most often lands between the
+/// same keys or a few keys further on, which is checked by stepping forward from the cursor
+/// before falling back to a binary search. A cursor should only be used with one curve.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct KeyCursor {
+    index: usize
+}
+
+impl KeyCursor {
+    pub fn new() -> KeyCursor {
+        KeyCursor { index: 0 }
+    }
+}
+
+/// The number of keys at or before `time`, i.e. the index of the first key after it.
+fn keys_until<T: Clone>(keys: &[Key<T>], time: f32) -> usize {
+    let mut low = 0;
+    let mut high = keys.len();
+    while low < high {
+        let mid = (low + high) / 2;
+        if keys[mid].0 > time {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    low
+}
+
+/// Keys a cursor steps forward over before binary searching the rest, enough to cover a curve
+/// with a key per frame sampled at a fraction of its frame rate, like 120 Hz motion capture
+/// played at 60 fps.
+const CURSOR_SCAN: usize = 8;
+
+fn keys_until_from<T: Clone>(keys: &[Key<T>], time: f32, cursor: &mut KeyCursor) -> usize {
+    let index = if cursor.index <= keys.len() && (cursor.index == 0 || keys[cursor.index - 1].0 <= time) {
+        let end = ::std::cmp::min(keys.len(), cursor.index + CURSOR_SCAN);
+        let mut index = cursor.index;
+        while index < end && keys[index].0 <= time {
+            index += 1;
+        }
+        if index < end || index == keys.len() {
+            index
+        } else {
+            end + keys_until(&keys[end..], time)
+        }
+    } else {
+        keys_until(keys, time)
+    };
+    cursor.index = index;
+    index
 }
 
 impl<T: Interpolateable + Debug + Clone> LinearKeyFrameCurve<T> {
-    pub fn to_discreet(&self, n_keys: usize, duration: f32) -> DiscreetKeyFrameCurve<T> {
-        let mut keys = vec![];
-        for i in 0..n_keys {
-            let p = duration * (i as f32 / n_keys as f32);
-            keys.push(Key(p, self.value(p)));
+    /// Key framed curves hold the value of their first key before it and of their last key
+    /// after it, so a curve with a single key has that key's value at all times.
+    pub fn new(keys: Vec<Key<T>>) -> Result<LinearKeyFrameCurve<T>, CurveError> {
+        try!(check_key_times(keys.iter().map(|k| k.0)));
+        Ok(LinearKeyFrameCurve { keys: keys })
+    }
+    /// The keys, which are never empty and are in time order.
+    pub fn keys(&self) -> &[Key<T>] {
+        &self.keys
+    }
+
+    fn value_between(&self, time: f32, keys_until: usize) -> T {
+        if keys_until == 0 {
+            return Interpolateable::interpolate(&self.keys[0].1, &self.keys[0].1, &0.0);
+        } else if keys_until == self.keys.len() {
+            let k = &self.keys[self.keys.len() - 1].1;
+            return Interpolateable::interpolate(k, k, &0.0);
         }
-        DiscreetKeyFrameCurve { keys: keys }
+        let key_before = &self.keys[keys_until - 1];
+        let key_after = &self.keys[keys_until];
+        let d = key_after.0 - key_before.0;
+        let p = (time - key_before.0) / d;
+        return Interpolateable::interpolate(&key_before.1, &key_after.1, &p);
+    }
+
+    /// Samples the curve at `n_keys` evenly spaced times from 0 to `duration`, both included.
+    pub fn to_discreet(&self, n_keys: usize, duration: f32) -> DiscreetKeyFrameCurve<T> {
+        let n_keys = if n_keys < 2 { 2 } else { n_keys };
+        let times = SampleTimes::with_count(0.0, duration, n_keys);
+        sample_curve(self, &times)
     }
 }
 
 impl<T: Interpolateable + Debug + Clone> Curve<T> for LinearKeyFrameCurve<T> {
     fn value(&self, time: f32) -> T {
-        let mut key_before = None;
-        let mut key_after = None;
-        for i in 0..self.keys.len() {
-            if self.keys[i].0 > time { break; }
-            else { key_before = Some(&self.keys[i]); }
+        self.value_between(time, keys_until(&self.keys, time))
+    }
+    fn value_with_cursor(&self, time: f32, cursor: &mut KeyCursor) -> T {
+        let index = keys_until_from(&self.keys, time, cursor);
+        self.value_between(time, index)
+    }
+    fn key_times(&self) -> Vec<f32> {
+        self.keys.iter().map(|k| k.0).collect()
+    }
+}
+
+/// Holds the value of each key until the next key is reached.
+#[derive(PartialEq, Debug)]
+pub struct StepKeyFrameCurve<T: Clone> {
+    keys: Vec<Key<T>>
+}
+
+impl<T: Debug + Clone> StepKeyFrameCurve<T> {
+    pub fn new(keys: Vec<Key<T>>) -> Result<StepKeyFrameCurve<T>, CurveError> {
+        try!(check_key_times(keys.iter().map(|k| k.0)));
+        Ok(StepKeyFrameCurve { keys: keys })
+    }
+    pub fn keys(&self) -> &[Key<T>] {
+        &self.keys
+    }
+}
+
+impl<T: Debug + Clone> Curve<T> for StepKeyFrameCurve<T> {
+    fn value(&self, time: f32) -> T {
+        self.value_with_cursor(time, &mut KeyCursor::new())
+    }
+    fn value_with_cursor(&self, time: f32, cursor: &mut KeyCursor) -> T {
+        match keys_until_from(&self.keys, time, cursor) {
+            0 => self.keys[0].1.clone(),
+            i => self.keys[i - 1].1.clone()
         }
-        for i in 0..self.keys.len() {
-            if self.keys[i].0 > time {
-                key_after = Some(&self.keys[i]);
-                break;
-            }
-        };
-        let key_before = match key_before {
-            Some(k) => k,
-            None => return Interpolateable::interpolate(&self.keys[0].1, &self.keys[0].1, &0.0)
-        };
-        let key_after = match key_after {
-            Some(k) => k,
-            None => {
-                let k = &self.keys[self.keys.len() - 1].1;
-                return Interpolateable::interpolate(k, k, &0.0)
+    }
+    fn key_times(&self) -> Vec<f32> {
+        self.keys.iter().map(|k| k.0).collect()
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct SplineKey {
+    pub time: f32,
+    pub value: Animatable,
+    pub in_tangent: Animatable,
+    pub out_tangent: Animatable
+}
+
+/// Cubic Hermite spline through the keys, with tangents given per key.
+/// Tangents are expressed in value per second.
+#[derive(PartialEq, Debug)]
+pub struct CubicSplineKeyFrameCurve {
+    keys: Vec<SplineKey>
+}
+
+impl CubicSplineKeyFrameCurve {
+    pub fn new(keys: Vec<SplineKey>) -> Result<CubicSplineKeyFrameCurve, CurveError> {
+        try!(check_key_times(keys.iter().map(|k| k.time)));
+        Ok(CubicSplineKeyFrameCurve { keys: keys })
+    }
+    pub fn keys(&self) -> &[SplineKey] {
+        &self.keys
+    }
+}
+
+impl CubicSplineKeyFrameCurve {
+    /// The keys around `time` and how far between them it is, if it is within the curve
+    fn segment(&self, time: f32) -> Option<(&SplineKey, &SplineKey, f32)> {
+        if time <= self.keys[0].time {
+            return None;
+        }
+        for i in 1..self.keys.len() {
+            if self.keys[i].time > time {
+                let a = &self.keys[i - 1];
+                let b = &self.keys[i];
+                return Some((a, b, (time - a.time) / (b.time - a.time)));
             }
-        };
-        let d = key_after.0 - key_before.0;
-        let p = (time - key_before.0) / d;
-        return Interpolateable::interpolate(&key_before.1, &key_after.1, &p);
+        }
+        None
+    }
+    /// Weighs the keys' values and tangents of a segment by the Hermite basis functions, or
+    /// their derivatives, given as [value a, out tangent a, value b, in tangent b].
+    fn hermite(a: &SplineKey, b: &SplineKey, basis: [f32; 4]) -> Animatable {
+        let d = b.time - a.time;
+        a.value.weighted(basis[0])
+            .add_weighted(d * basis[1], &a.out_tangent)
+            .add_weighted(basis[2], &b.value)
+            .add_weighted(d * basis[3], &b.in_tangent)
+    }
+}
+
+impl Curve<Animatable> for CubicSplineKeyFrameCurve {
+    fn value(&self, time: f32) -> Animatable {
+        match self.segment(time) {
+            Some((a, b, p)) => {
+                let p2 = p * p;
+                let p3 = p2 * p;
+                CubicSplineKeyFrameCurve::hermite(a, b, [2.0 * p3 - 3.0 * p2 + 1.0, p3 - 2.0 * p2 + p, -2.0 * p3 + 3.0 * p2, p3 - p2])
+            },
+            None if time <= self.keys[0].time => self.keys[0].value.clone(),
+            None => self.keys[self.keys.len() - 1].value.clone()
+        }
+    }
+    fn key_times(&self) -> Vec<f32> {
+        self.keys.iter().map(|k| k.time).collect()
     }
 }
 
 #[derive(PartialEq, Debug)]
 pub struct DiscreetKeyFrameCurve<T: Clone> {
-    pub keys: Vec<Key<T>>
+    keys: Vec<Key<T>>
+}
+
+impl<T: Debug + Clone> DiscreetKeyFrameCurve<T> {
+    pub fn new(keys: Vec<Key<T>>) -> Result<DiscreetKeyFrameCurve<T>, CurveError> {
+        try!(check_key_times(keys.iter().map(|k| k.0)));
+        Ok(DiscreetKeyFrameCurve { keys: keys })
+    }
+    pub fn keys(&self) -> &[Key<T>] {
+        &self.keys
+    }
 }
 
-impl<T: Interpolateable + Debug + Clone> Curve<T> for DiscreetKeyFrameCurve<T> {
+/// Holds the value of each key until the next key, like `StepKeyFrameCurve`, but finds the key
+/// from the time directly, which takes constant time when the keys are evenly spaced.
+impl<T: Debug + Clone> Curve<T> for DiscreetKeyFrameCurve<T> {
     fn value(&self, time: f32) -> T {
-        let i = (time * self.keys.len() as f32) as usize;
-        if i < 0 {
-            return self.keys[0].1.clone()
-        } else if (i >= self.keys.len()) {
-            return self.keys[self.keys.len() - 1].1.clone()
+        let first = self.keys[0].0;
+        let last = self.keys.len() - 1;
+        if time < first || last == 0 {
+            return self.keys[0].1.clone();
+        } else if time >= self.keys[last].0 {
+            return self.keys[last].1.clone();
+        }
+        let interval = (self.keys[last].0 - first) / last as f32;
+        let guess = ((time - first) / interval) as usize;
+        // Keys that are not evenly spaced, or rounding, can put the guess off
+        let i = if guess < last && self.keys[guess].0 <= time && self.keys[guess + 1].0 > time {
+            guess
         } else {
-            return self.keys[i as usize].1.clone();
+            keys_until(&self.keys, time) - 1
+        };
+        self.keys[i].1.clone()
+    }
+    fn key_times(&self) -> Vec<f32> {
+        self.keys.iter().map(|k| k.0).collect()
+    }
+}
+
+impl Differentiable for FixedValueCurve<Animatable> {
+    fn velocity(&self, _: f32) -> Animatable { zero(&self.value) }
+    fn acceleration(&self, _: f32) -> Animatable { zero(&self.value) }
+}
+
+impl Differentiable for LinearKeyFrameCurve<Animatable> {
+    fn velocity(&self, time: f32) -> Animatable {
+        let i = keys_until(&self.keys, time);
+        if i == 0 || i == self.keys.len() {
+            return zero(&self.keys[0].1);
+        }
+        let (a, b) = (&self.keys[i - 1], &self.keys[i]);
+        b.1.add_weighted(-1.0, &a.1).weighted(1.0 / (b.0 - a.0))
+    }
+    fn acceleration(&self, _: f32) -> Animatable { zero(&self.keys[0].1) }
+}
+
+impl Differentiable for StepKeyFrameCurve<Animatable> {
+    fn velocity(&self, _: f32) -> Animatable { zero(&self.keys[0].1) }
+    fn acceleration(&self, _: f32) -> Animatable { zero(&self.keys[0].1) }
+}
+
+impl Differentiable for DiscreetKeyFrameCurve<Animatable> {
+    fn velocity(&self, _: f32) -> Animatable { zero(&self.keys[0].1) }
+    fn acceleration(&self, _: f32) -> Animatable { zero(&self.keys[0].1) }
+}
+
+impl Differentiable for CubicSplineKeyFrameCurve {
+    fn velocity(&self, time: f32) -> Animatable {
+        match self.segment(time) {
+            Some((a, b, p)) => {
+                let p2 = p * p;
+                CubicSplineKeyFrameCurve::hermite(a, b, [6.0 * p2 - 6.0 * p, 3.0 * p2 - 4.0 * p + 1.0, -6.0 * p2 + 6.0 * p, 3.0 * p2 - 2.0 * p])
+                    .weighted(1.0 / (b.time - a.time))
+            },
+            None => zero(&self.keys[0].value)
+        }
+    }
+    fn acceleration(&self, time: f32) -> Animatable {
+        match self.segment(time) {
+            Some((a, b, p)) => {
+                let d = b.time - a.time;
+                CubicSplineKeyFrameCurve::hermite(a, b, [12.0 * p - 6.0, 6.0 * p - 4.0, -12.0 * p + 6.0, 6.0 * p - 2.0])
+                    .weighted(1.0 / (d * d))
+            },
+            None => zero(&self.keys[0].value)
         }
     }
 }
 
+fn sample_curve<T: Clone, C: Curve<T> + ?Sized>(curve: &C, times: &SampleTimes) -> DiscreetKeyFrameCurve<T> {
+    DiscreetKeyFrameCurve {
+        keys: (0..times.count()).map(|i| {
+            let time = times.time(i);
+            Key(time, curve.value(time))
+        }).collect()
+    }
+}
+
+/// Bakes any curve into evenly spaced keys, taken at least `sample_rate` times per second from
+/// `start` to `end`, both included.
+pub fn resample<T: Clone, C: Curve<T> + ?Sized>(curve: &C, sample_rate: f32, start: f32, end: f32) -> DiscreetKeyFrameCurve<T> {
+    sample_curve(curve, &SampleTimes::new(start, end, sample_rate))
+}
+
+fn keys_pon(keys: &Vec<Key<Animatable>>) -> Pon {
+    Pon::Array(keys.iter().map(|&Key(time, ref value)| Pon::Array(vec![time.to_pon(), value.to_key_pon()])).collect())
+}
+
+impl ToPon for FixedValueCurve<Animatable> {
+    fn to_pon(&self) -> Pon {
+        typed_pon("fixed_value", object_pon(vec![("value", self.value.to_key_pon())]))
+    }
+}
+
+impl ToPon for LinearKeyFrameCurve<Animatable> {
+    fn to_pon(&self) -> Pon {
+        typed_pon("key_framed", object_pon(vec![("keys", keys_pon(&self.keys))]))
+    }
+}
+
+impl ToPon for StepKeyFrameCurve<Animatable> {
+    fn to_pon(&self) -> Pon {
+        typed_pon("key_framed", object_pon(vec![
+            ("keys", keys_pon(&self.keys)),
+            ("interpolation", Pon::String("step".to_string()))
+        ]))
+    }
+}
+
+impl ToPon for DiscreetKeyFrameCurve<Animatable> {
+    fn to_pon(&self) -> Pon {
+        typed_pon("key_framed", object_pon(vec![
+            ("keys", keys_pon(&self.keys)),
+            ("interpolation", Pon::String("discreet".to_string()))
+        ]))
+    }
+}
+
+impl ToPon for SplineKey {
+    fn to_pon(&self) -> Pon {
+        object_pon(vec![
+            ("time", self.time.to_pon()),
+            ("value", self.value.to_key_pon()),
+            ("in_tangent", self.in_tangent.to_key_pon()),
+            ("out_tangent", self.out_tangent.to_key_pon())
+        ])
+    }
+}
+
+impl ToPon for CubicSplineKeyFrameCurve {
+    fn to_pon(&self) -> Pon {
+        typed_pon("key_framed", object_pon(vec![
+            ("keys", Pon::Array(self.keys.iter().map(|key| key.to_pon()).collect())),
+            ("interpolation", Pon::String("cubic_spline".to_string()))
+        ]))
+    }
+}
+
 #[test]
 fn test_key_frame_single() {
     let kf = LinearKeyFrameCurve {
@@ -110,6 +513,31 @@ fn test_key_frame_vector() {
     assert_eq!(kf.value(1.1), Vector2::new(1.0, 1.0));
 }
 
+#[test]
+fn test_step_key_frame() {
+    let kf = StepKeyFrameCurve {
+        keys: vec![Key(0.0, 0.0), Key(1.0, 1.0), Key(2.0, 5.0)]
+    };
+    assert_eq!(kf.value(-0.1), 0.0);
+    assert_eq!(kf.value(0.9), 0.0);
+    assert_eq!(kf.value(1.0), 1.0);
+    assert_eq!(kf.value(3.0), 5.0);
+}
+
+#[test]
+fn test_cubic_spline_key_frame() {
+    let zero = Animatable::new_float(0.0);
+    let kf = CubicSplineKeyFrameCurve {
+        keys: vec![
+            SplineKey { time: 0.0, value: Animatable::new_float(0.0), in_tangent: zero.clone(), out_tangent: zero.clone() },
+            SplineKey { time: 2.0, value: Animatable::new_float(1.0), in_tangent: zero.clone(), out_tangent: zero.clone() }
+        ]
+    };
+    assert_eq!(kf.value(0.0), Animatable::new_float(0.0));
+    assert_eq!(kf.value(1.0), Animatable::new_float(0.5));
+    assert_eq!(kf.value(2.0), Animatable::new_float(1.0));
+}
+
 #[test]
 fn test_key_frame_multi_keys() {
     let kf = LinearKeyFrameCurve {
@@ -122,3 +550,83 @@ fn test_key_frame_multi_keys() {
     assert_eq!(kf.value(21.5), 3.0);
     assert_eq!(kf.value(30.0), 5.0);
 }
+
+#[test]
+fn test_key_frame_constructors() {
+    assert_eq!(LinearKeyFrameCurve::<f32>::new(vec![]), Err(CurveError::NoKeys));
+    assert_eq!(StepKeyFrameCurve::new(vec![Key(0.0, 0.0), Key(2.0, 1.0), Key(1.0, 2.0)]), Err(CurveError::UnsortedKeys { index: 2 }));
+    let kf = LinearKeyFrameCurve::new(vec![Key(1.0, 3.0)]).unwrap();
+    assert_eq!(kf.value(0.0), 3.0);
+    assert_eq!(kf.value(1.0), 3.0);
+    assert_eq!(kf.value(2.0), 3.0);
+}
+
+#[test]
+fn test_key_frame_cursor() {
+    let kf = LinearKeyFrameCurve {
+        keys: vec![Key(0.0, 0.0), Key(1.0, 1.0), Key(2.0, 0.0), Key(3.0, 1.0)]
+    };
+    let mut cursor = KeyCursor::new();
+    for &time in &[-1.0, 0.5, 0.7, 1.5, 2.5, 4.0, 0.25, 2.0, 1.0] {
+        assert_eq!(kf.value_with_cursor(time, &mut cursor), kf.value(time));
+    }
+}
+
+#[test]
+fn test_key_frame_cursor_skips_keys() {
+    let kf = LinearKeyFrameCurve::new((0..40).map(|i| Key(i as f32, i as f32 * 2.0)).collect()).unwrap();
+    let mut cursor = KeyCursor::new();
+    for &(time, index) in &[(0.5, 1), (3.5, 4), (6.5, 7), (13.5, 14), (30.5, 31), (50.0, 40), (2.5, 3)] {
+        assert_eq!(kf.value_with_cursor(time, &mut cursor), kf.value(time));
+        assert_eq!(cursor.index, index);
+    }
+}
+
+#[test]
+fn test_resample() {
+    let kf = LinearKeyFrameCurve {
+        keys: vec![Key(0.0, 0.0), Key(1.0, 1.0), Key(2.0, 0.0)]
+    };
+    let resampled = resample(&kf, 2.0, 0.0, 2.0);
+    assert_eq!(resampled.key_times(), vec![0.0, 0.5, 1.0, 1.5, 2.0]);
+    for &Key(time, value) in &resampled.keys {
+        assert_eq!(resampled.value(time), value);
+        assert_eq!(value, kf.value(time));
+    }
+    assert_eq!(resampled.value(0.7), 0.5);
+    assert_eq!(resampled.value(2.5), 0.0);
+    assert_eq!(kf.to_discreet(3, 2.0).key_times(), vec![0.0, 1.0, 2.0]);
+}
+
+#[test]
+fn test_discreet_uneven_keys() {
+    let kf = DiscreetKeyFrameCurve::new(vec![Key(0.0, 0.0), Key(0.1, 1.0), Key(0.2, 2.0), Key(3.0, 3.0)]).unwrap();
+    assert_eq!(kf.value(0.05), 0.0);
+    assert_eq!(kf.value(0.15), 1.0);
+    assert_eq!(kf.value(1.5), 2.0);
+    assert_eq!(kf.value(3.5), 3.0);
+}
+
+#[test]
+fn test_derivatives() {
+    let zero = Animatable::new_float(0.0);
+    let spline = CubicSplineKeyFrameCurve {
+        keys: vec![
+            SplineKey { time: 0.0, value: Animatable::new_float(0.0), in_tangent: zero.clone(), out_tangent: Animatable::new_float(1.0) },
+            SplineKey { time: 2.0, value: Animatable::new_float(1.0), in_tangent: zero.clone(), out_tangent: zero.clone() }
+        ]
+    };
+    for &time in &[0.25, 0.5, 1.0, 1.5] {
+        let exact = spline.velocity(time).value[0];
+        let h = 0.001;
+        let estimate = (spline.value(time + h).value[0] - spline.value(time - h).value[0]) / (2.0 * h);
+        assert!((exact - estimate).abs() < 0.01);
+    }
+    assert_eq!(spline.velocity(0.0001).value[0].round(), 1.0);
+    let linear = LinearKeyFrameCurve {
+        keys: vec![Key(0.0, Animatable::new(vec![0.0, 1.0])), Key(2.0, Animatable::new(vec![1.0, -1.0]))]
+    };
+    assert_eq!(linear.velocity(1.0), Animatable::new(vec![0.5, -1.0]));
+    assert_eq!(linear.velocity(3.0), Animatable::new(vec![0.0, 0.0]));
+    assert_eq!(linear.acceleration(1.0), Animatable::new(vec![0.0, 0.0]));
+}
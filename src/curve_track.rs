@@ -1,4 +1,5 @@
 
+use std::cell::Cell;
 use time::*;
 
 use curve::*;
@@ -6,6 +7,7 @@ use track::*;
 use pyramid::pon::*;
 use cgmath::*;
 use animatable::*;
+use oscillator::*;
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Loop {
@@ -24,48 +26,138 @@ pub enum CurveTime {
 
 #[derive(Debug)]
 pub struct CurveTrack {
-    pub curve: Box<Curve<Animatable>>,
+    pub curve: Box<AnimationCurve>,
     pub offset: Duration,
     pub property: NamedPropRef,
     pub loop_type: Loop,
     pub duration: Duration,
-    pub curve_time: CurveTime
+    pub curve_time: CurveTime,
+    /// Where the previous key lookup ended, since tracks are mostly sampled at increasing times
+    cursor: Cell<KeyCursor>
 }
 
 impl CurveTrack {
-    pub fn new_fixed_value(property: NamedPropRef, value: Animatable) -> CurveTrack {
+    /// A track playing `curve` from zero to `duration`, with the curve's keys in seconds.
+    pub fn new(property: NamedPropRef, curve: Box<AnimationCurve>, duration: Duration, loop_type: Loop) -> CurveTrack {
         CurveTrack {
-            curve: Box::new(FixedValueCurve { value: value }),
+            curve: curve,
             offset: Duration::zero(),
             property: property,
-            loop_type: Loop::Forever,
-            duration: Duration::weeks(1),
-            curve_time: CurveTime::Absolute
+            loop_type: loop_type,
+            duration: duration,
+            curve_time: CurveTime::Absolute,
+            cursor: Cell::new(KeyCursor::new())
         }
     }
-}
 
-impl Track for CurveTrack {
-    fn value_at(&self, time: Duration) -> Vec<(NamedPropRef, Animatable)> {
+    pub fn new_fixed_value(property: NamedPropRef, value: Animatable) -> CurveTrack {
+        CurveTrack::new_unbounded(property, Box::new(FixedValueCurve { value: value }))
+    }
+
+    /// A track for curves that have a value at any time, which plays for a week and then loops.
+    pub fn new_unbounded(property: NamedPropRef, curve: Box<AnimationCurve>) -> CurveTrack {
+        CurveTrack::new(property, curve, Duration::weeks(1), Loop::Forever)
+    }
+
+    /// The time on the curve at a time on the track, if the track is playing then
+    fn curve_time_at(&self, time: Duration) -> Option<f32> {
         let time = time - self.offset;
         let time = if time > self.duration {
             if self.loop_type == Loop::Forever {
-                Duration::milliseconds(time.num_milliseconds() % self.duration.num_milliseconds())
+                if self.duration > Duration::zero() {
+                    Duration::milliseconds(time.num_milliseconds() % self.duration.num_milliseconds())
+                } else {
+                    Duration::zero()
+                }
             } else {
-                return vec![]
+                return None
             }
         } else {
             time
         };
-        let time = match self.curve_time {
+        Some(match self.curve_time {
             CurveTime::Absolute => time.num_milliseconds() as f32 / 1000.0,
-            CurveTime::Relative => time.num_milliseconds() as f32 / self.duration.num_milliseconds() as f32
+            CurveTime::Relative if self.duration > Duration::zero() => time.num_milliseconds() as f32 / self.duration.num_milliseconds() as f32,
+            CurveTime::Relative => 0.0
+        })
+    }
+}
+
+impl Track for CurveTrack {
+    fn value_at(&self, time: Duration) -> Vec<(NamedPropRef, Animatable)> {
+        match self.curve_time_at(time) {
+            Some(time) => {
+                let mut cursor = self.cursor.get();
+                let value = self.curve.value_with_cursor(time, &mut cursor);
+                self.cursor.set(cursor);
+                vec![(self.property.clone(), value)]
+            },
+            None => vec![]
+        }
+    }
+    fn velocity_at(&self, time: Duration, _properties: &PropertySource) -> Vec<(NamedPropRef, Animatable)> {
+        let velocity = match self.curve_time_at(time) {
+            Some(time) => self.curve.velocity(time),
+            None => return vec![]
         };
-        return vec![(self.property.clone(), self.curve.value(time))];
+        let velocity = match self.curve_time {
+            CurveTime::Absolute => velocity,
+            CurveTime::Relative if self.duration > Duration::zero() => velocity.weighted(1000.0 / self.duration.num_milliseconds() as f32),
+            CurveTime::Relative => velocity.weighted(0.0)
+        };
+        vec![(self.property.clone(), velocity)]
+    }
+    fn duration(&self) -> Duration {
+        self.offset + self.duration
+    }
+    fn properties(&self) -> Vec<NamedPropRef> {
+        vec![self.property.clone()]
+    }
+    fn key_times(&self) -> Vec<f32> {
+        let offset = self.offset.num_milliseconds() as f32 / 1000.0;
+        let duration = self.duration.num_milliseconds() as f32 / 1000.0;
+        self.curve.key_times().into_iter().map(|t| match self.curve_time {
+            CurveTime::Absolute => offset + t,
+            CurveTime::Relative => offset + t * duration
+        }).collect()
     }
 }
 
 
+impl ToPon for CurveTrack {
+    fn to_pon(&self) -> Pon {
+        let mut pon = self.curve.to_pon();
+        if let Pon::TypedPon(box TypedPon { data: Pon::Object(ref mut fields), .. }) = pon {
+            fields.insert("property".to_string(), Pon::Reference(self.property.clone()));
+            fields.insert("duration".to_string(), (self.duration.num_milliseconds() as f32 / 1000.0).to_pon());
+            // Oscillator curves use `offset` for their own value offset
+            if self.offset != Duration::zero() {
+                fields.insert("start_offset".to_string(), (self.offset.num_milliseconds() as f32 / 1000.0).to_pon());
+            }
+            fields.insert("loop".to_string(), self.loop_type.to_pon());
+            fields.insert("curve_time".to_string(), self.curve_time.to_pon());
+        }
+        pon
+    }
+}
+
+impl ToPon for Loop {
+    fn to_pon(&self) -> Pon {
+        Pon::String(match self {
+            &Loop::Forever => "forever",
+            &Loop::Once => "once"
+        }.to_string())
+    }
+}
+impl ToPon for CurveTime {
+    fn to_pon(&self) -> Pon {
+        Pon::String(match self {
+            &CurveTime::Absolute => "absolute",
+            &CurveTime::Relative => "relative"
+        }.to_string())
+    }
+}
+
 impl Translatable<Loop> for Pon {
     fn inner_translate(&self, context: &mut TranslateContext) -> Result<Loop, PonTranslateErr> {
         match try!(self.translate::<String>(context)).as_str() {
@@ -93,19 +185,68 @@ impl Translatable<Key<Animatable>> for Pon {
                 let value = try!(self.field_as("value", context));
                 Ok(Key(time, value))
             },
-            &Pon::Array(ref arr) => {
+            &Pon::Array(ref arr) if arr.len() == 2 => {
                 let time: f32 = try!(arr[0].translate::<f32>(context));
                 let value = try!(arr[1].translate(context));
                 Ok(Key(time, value))
             },
-            &Pon::FloatArray(ref arr) => Ok(Key(arr[0], Animatable { value: vec![arr[1]] })),
+            &Pon::FloatArray(ref arr) if arr.len() == 2 => Ok(Key(arr[0], Animatable { value: vec![arr[1]] })),
             _ => {
-                Err(PonTranslateErr::MismatchType { expected: "Object or Array".to_string(), found: format!("{:?}", self) })
+                Err(PonTranslateErr::MismatchType { expected: "Object or [time, value]".to_string(), found: format!("{:?}", self) })
             }
         }
     }
 }
 
+impl Translatable<SplineKey> for Pon {
+    fn inner_translate(&self, context: &mut TranslateContext) -> Result<SplineKey, PonTranslateErr> {
+        let value: Animatable = try!(self.field_as("value", context));
+        let zero = value.weighted(0.0);
+        Ok(SplineKey {
+            time: try!(self.field_as::<f32>("time", context)),
+            in_tangent: try!(self.field_as_or("in_tangent", zero.clone(), context)),
+            out_tangent: try!(self.field_as_or("out_tangent", zero, context)),
+            value: value
+        })
+    }
+}
+
+/// Reads the optional duration, start offset, loop and curve time of a track made with `new_unbounded`.
+fn translate_unbounded(mut track: CurveTrack, data: &Pon, context: &mut TranslateContext) -> Result<CurveTrack, PonTranslateErr> {
+    let duration: f32 = try!(data.field_as_or("duration", track.duration.num_milliseconds() as f32 / 1000.0, context));
+    track.duration = Duration::milliseconds((duration*1000.0) as i64);
+    let offset: f32 = try!(data.field_as_or("start_offset", 0.0, context));
+    track.offset = Duration::milliseconds((offset*1000.0) as i64);
+    track.loop_type = try!(data.field_as_or("loop", track.loop_type.clone(), context));
+    track.curve_time = try!(data.field_as_or("curve_time", track.curve_time.clone(), context));
+    Ok(track)
+}
+
+fn curve_err(err: CurveError) -> PonTranslateErr {
+    PonTranslateErr::InvalidValue { value: err.to_string() }
+}
+
+/// Reads the `keys` and `interpolation` fields of a key framed curve.
+pub fn translate_key_framed_curve(data: &Pon, context: &mut TranslateContext) -> Result<Box<AnimationCurve>, PonTranslateErr> {
+    let interpolation: String = try!(data.field_as_or("interpolation", "linear".to_string(), context));
+    let curve: Box<AnimationCurve> = match interpolation.as_str() {
+        "cubic_spline" => {
+            let keys: PonAutoVec<SplineKey> = try!(data.field_as("keys", context));
+            Box::new(try!(CubicSplineKeyFrameCurve::new(keys.0).map_err(curve_err)))
+        },
+        _ => {
+            let keys: PonAutoVec<Key<Animatable>> = try!(data.field_as("keys", context));
+            match interpolation.as_str() {
+                "linear" => Box::new(try!(LinearKeyFrameCurve::new(keys.0).map_err(curve_err))),
+                "step" => Box::new(try!(StepKeyFrameCurve::new(keys.0).map_err(curve_err))),
+                "discreet" => Box::new(try!(DiscreetKeyFrameCurve::new(keys.0).map_err(curve_err))),
+                _ => return Err(PonTranslateErr::InvalidValue { value: interpolation.clone() })
+            }
+        }
+    };
+    Ok(curve)
+}
+
 impl Translatable<CurveTrack> for Pon {
     fn inner_translate(&self, context: &mut TranslateContext) -> Result<CurveTrack, PonTranslateErr> {
         self.as_typed(|&TypedPon { ref type_name, ref data }| {
@@ -113,27 +254,24 @@ impl Translatable<CurveTrack> for Pon {
                 "key_framed" => {
                     let property: &NamedPropRef = try!(try!(data.field("property")).as_reference());
                     let duration: f32 = try!(data.field_as_or("duration", 1.0, context));
+                    let offset: f32 = try!(data.field_as_or("start_offset", 0.0, context));
                     let loop_type = try!(data.field_as_or("loop", Loop::Once, context));
                     let curve_time = try!(data.field_as_or("curve_time", CurveTime::Absolute, context));
-                    let curve: Box<Curve<Animatable>> = {
-                        let keys: PonAutoVec<Key<Animatable>> = try!(data.field_as("keys", context));
-                        Box::new(LinearKeyFrameCurve {
-                            keys: keys.0
-                        })
-                    };
-                    Ok(CurveTrack {
-                        curve: curve,
-                        offset: Duration::zero(),
-                        property: property.clone(),
-                        loop_type: loop_type,
-                        duration: Duration::milliseconds((duration*1000.0) as i64),
-                        curve_time: curve_time
-                    })
+                    let curve = try!(translate_key_framed_curve(data, context));
+                    let mut track = CurveTrack::new(property.clone(), curve, Duration::milliseconds((duration*1000.0) as i64), loop_type);
+                    track.offset = Duration::milliseconds((offset*1000.0) as i64);
+                    track.curve_time = curve_time;
+                    Ok(track)
                 },
                 "fixed_value" => {
                     let property: &NamedPropRef = try!(try!(data.field("property")).as_reference());
                     let value = try!(data.field_as::<Animatable>("value", context));
-                    Ok(CurveTrack::new_fixed_value(property.clone(), value))
+                    translate_unbounded(CurveTrack::new_fixed_value(property.clone(), value), data, context)
+                },
+                s if OSCILLATOR_TYPES.contains(&s) => {
+                    let property: &NamedPropRef = try!(try!(data.field("property")).as_reference());
+                    let curve = try!(self.translate::<OscillatorCurve>(context));
+                    translate_unbounded(CurveTrack::new_unbounded(property.clone(), Box::new(curve)), data, context)
                 },
                 s @ _ => Err(PonTranslateErr::UnrecognizedType(s.to_string()))
             }
@@ -145,18 +283,16 @@ impl Translatable<CurveTrack> for Pon {
 
 #[test]
 fn test_animation() {
-    let kf = CurveTrack {
-        curve: Box::new(LinearKeyFrameCurve {
-            keys: vec![Key(0.0, Animatable::new_float(0.0)), Key(1.0, Animatable::new_float(1.0))]
-        }),
-        offset: Duration::zero(),
-        property: NamedPropRef::new(EntityPath::This, "x"),
-        loop_type: Loop::Once,
-        duration: Duration::seconds(1),
-        curve_time: CurveTime::Absolute
-    };
+    let kf = CurveTrack::new(
+        NamedPropRef::new(EntityPath::This, "x"),
+        Box::new(LinearKeyFrameCurve::new(vec![Key(0.0, Animatable::new_float(0.0)), Key(1.0, Animatable::new_float(1.0))]).unwrap()),
+        Duration::seconds(1),
+        Loop::Once
+    );
     assert_eq!(kf.value_at(Duration::milliseconds(100)), vec![(NamedPropRef::new(EntityPath::This, "x"), Animatable::new_float(0.1))]);
     assert_eq!(kf.value_at(Duration::milliseconds(600)), vec![(NamedPropRef::new(EntityPath::This, "x"), Animatable::new_float(0.6))]);
+    // Going back in time moves the key cursor back too
+    assert_eq!(kf.value_at(Duration::milliseconds(100)), vec![(NamedPropRef::new(EntityPath::This, "x"), Animatable::new_float(0.1))]);
 }
 
 #[test]
@@ -184,3 +320,60 @@ fn test_animation_from_pon_multivalue() {
         .unwrap().translate(&mut TranslateContext::empty()).unwrap();
     assert_eq!(kf.value_at(Duration::milliseconds(500)), vec![(NamedPropRef::new(EntityPath::This, "x"), Animatable::new(vec![-1.0, 5.0]))]);
 }
+
+#[test]
+fn test_animation_to_pon_roundtrip() {
+    let sources = vec![
+        "key_framed { property: this.x, keys: [[0.0, 0.0], [1.0, [2.0, 3.0]]], loop: 'forever', duration: 2.0 }",
+        "key_framed { property: this.x, keys: [[0.0, 0.0], [1.0, 1.0]], start_offset: 0.5 }",
+        "key_framed { property: this.x, keys: [[0.0, 1.0], [0.5, 2.0]], interpolation: 'step', curve_time: 'relative' }",
+        "key_framed { property: this.x, keys: [{ time: 0.0, value: 0.0, out_tangent: 1.0 }, { time: 1.0, value: 1.0 }], interpolation: 'cubic_spline' }",
+        "fixed_value { property: this.y, value: [1.0, 2.0, 3.0] }",
+        "square { property: this.y, amplitude: [1.0, 2.0], frequency: 2.0, duty: 0.3 }",
+        "noise { property: this.y, seed: 3.0, offset: 1.0 }",
+        "sine { property: this.y, offset: 2.0, start_offset: 0.1 }"
+    ];
+    for source in sources {
+        let track: CurveTrack = Pon::from_string(source).unwrap().translate(&mut TranslateContext::empty()).unwrap();
+        let pon = track.to_pon();
+        let roundtrip: CurveTrack = pon.translate(&mut TranslateContext::empty()).unwrap();
+        assert_eq!(roundtrip.to_pon(), pon);
+        assert_eq!(roundtrip.value_at(Duration::milliseconds(300)), track.value_at(Duration::milliseconds(300)));
+    }
+}
+
+#[test]
+fn test_oscillator_start_offset_roundtrip() {
+    let track: CurveTrack = Pon::from_string("sawtooth { property: this.y, offset: 2.0, start_offset: 0.25 }")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    assert_eq!(track.offset, Duration::milliseconds(250));
+    let roundtrip: CurveTrack = track.to_pon().translate(&mut TranslateContext::empty()).unwrap();
+    assert_eq!(roundtrip.offset, Duration::milliseconds(250));
+    assert_eq!(roundtrip.value_at(Duration::milliseconds(750)), vec![(NamedPropRef::new(EntityPath::This, "y"), Animatable::new_float(2.0))]);
+}
+
+#[test]
+fn test_animation_from_pon_invalid_keys() {
+    for source in vec!["key_framed { property: this.x, keys: [] }", "key_framed { property: this.x, keys: [[1.0, 0.0], [0.0, 1.0]] }"] {
+        let res: Result<CurveTrack, PonTranslateErr> = Pon::from_string(source).unwrap().translate(&mut TranslateContext::empty());
+        assert!(res.is_err());
+    }
+}
+
+#[test]
+fn test_animation_velocity() {
+    let track: CurveTrack = Pon::from_string(
+        "key_framed { property: this.x, keys: [[0.0, 0.0], [1.0, 4.0]], curve_time: 'relative', duration: 2.0 }")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    assert_eq!(track.velocity_at(Duration::milliseconds(500), &NoProperties), vec![(NamedPropRef::new(EntityPath::This, "x"), Animatable::new_float(2.0))]);
+    assert_eq!(track.velocity_at(Duration::seconds(3), &NoProperties), vec![]);
+}
+
+#[test]
+fn test_animation_zero_duration() {
+    let track: CurveTrack = Pon::from_string(
+        "key_framed { property: this.x, keys: [[0.0, 2.0]], loop: 'forever', curve_time: 'relative', duration: 0.0 }")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    assert_eq!(track.value_at(Duration::milliseconds(1500)), vec![(NamedPropRef::new(EntityPath::This, "x"), Animatable::new_float(2.0))]);
+    assert_eq!(track.velocity_at(Duration::milliseconds(1500), &NoProperties), vec![(NamedPropRef::new(EntityPath::This, "x"), Animatable::new_float(0.0))]);
+}
@@ -0,0 +1,90 @@
+
+use time::*;
+use pyramid::pon::*;
+use animatable::*;
+use curve::*;
+use curve_track::*;
+use track::*;
+
+/// Drives a property from the value of another property instead of from time, by looking up
+/// the input value on a curve, e.g. the angle of a door from how far it is open.
+#[derive(Debug)]
+pub struct DrivenTrack {
+    pub property: NamedPropRef,
+    pub input: NamedPropRef,
+    /// Which component of the input drives the curve
+    pub input_component: usize,
+    pub curve: Box<AnimationCurve>
+}
+
+impl Track for DrivenTrack {
+    fn value_at(&self, time: Duration) -> Vec<(NamedPropRef, Animatable)> {
+        self.value_at_with(time, &NoProperties)
+    }
+    fn value_at_with(&self, _time: Duration, properties: &PropertySource) -> Vec<(NamedPropRef, Animatable)> {
+        match properties.property_value(&self.input).and_then(|input| input.value.get(self.input_component).cloned()) {
+            Some(input) => vec![(self.property.clone(), self.curve.value(input))],
+            None => vec![]
+        }
+    }
+    fn duration(&self) -> Duration {
+        Duration::weeks(1)
+    }
+    fn properties(&self) -> Vec<NamedPropRef> {
+        vec![self.property.clone()]
+    }
+}
+
+impl ToPon for DrivenTrack {
+    fn to_pon(&self) -> Pon {
+        let mut data = match self.curve.to_pon() {
+            Pon::TypedPon(box TypedPon { data, .. }) => data,
+            _ => object_pon(vec![])
+        };
+        if let Pon::Object(ref mut fields) = data {
+            fields.insert("property".to_string(), Pon::Reference(self.property.clone()));
+            fields.insert("input".to_string(), Pon::Reference(self.input.clone()));
+            fields.insert("input_component".to_string(), (self.input_component as f32).to_pon());
+        }
+        typed_pon("driven", data)
+    }
+}
+
+impl Translatable<DrivenTrack> for Pon {
+    fn inner_translate(&self, context: &mut TranslateContext) -> Result<DrivenTrack, PonTranslateErr> {
+        self.as_typed(|&TypedPon { ref data, .. }| -> Result<DrivenTrack, PonTranslateErr> {
+            let property: &NamedPropRef = try!(try!(data.field("property")).as_reference());
+            let input: &NamedPropRef = try!(try!(data.field("input")).as_reference());
+            let input_component: f32 = try!(data.field_as_or("input_component", 0.0, context));
+            Ok(DrivenTrack {
+                property: property.clone(),
+                input: input.clone(),
+                input_component: input_component as usize,
+                curve: try!(translate_key_framed_curve(data, context))
+            })
+        })
+    }
+}
+
+
+#[cfg(test)]
+struct OpenAmount(f32);
+
+#[cfg(test)]
+impl PropertySource for OpenAmount {
+    fn property_value(&self, property: &NamedPropRef) -> Option<Animatable> {
+        if property == &NamedPropRef::new(EntityPath::This, "open_amount") { Some(Animatable::new_float(self.0)) } else { None }
+    }
+}
+
+#[test]
+fn test_driven_track() {
+    let source = "driven { property: this.angle, input: this.open_amount, keys: [[0.0, 0.0], [0.5, 80.0], [1.0, 90.0]] }";
+    let track: DrivenTrack = Pon::from_string(source).unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    let angle = NamedPropRef::new(EntityPath::This, "angle");
+    assert_eq!(track.value_at_with(Duration::seconds(10), &OpenAmount(0.25)), vec![(angle.clone(), Animatable::new_float(40.0))]);
+    assert_eq!(track.value_at_with(Duration::zero(), &OpenAmount(0.75)), vec![(angle.clone(), Animatable::new_float(85.0))]);
+    assert_eq!(track.value_at(Duration::zero()), vec![]);
+    let roundtrip: DrivenTrack = track.to_pon().translate(&mut TranslateContext::empty()).unwrap();
+    assert_eq!(roundtrip.to_pon(), track.to_pon());
+}
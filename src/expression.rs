@@ -0,0 +1,425 @@
+
+use std::fmt;
+use std::f32::consts::{PI, E};
+use time::*;
+use pyramid::pon::*;
+use animatable::*;
+use track::*;
+
+macro_rules! try_opt {
+    ($e:expr) => (match $e { Some(value) => value, None => return None })
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct ExpressionError {
+    /// Character offset into the expression
+    pub position: usize,
+    pub message: String
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at {}: {}", self.position, self.message)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+enum Token {
+    Number(f32),
+    Name(String),
+    Operator(char),
+    Open,
+    Close,
+    Comma
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, ExpressionError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let token = if c.is_digit(10) || (c == '.' && chars.get(i + 1).map(|c| c.is_digit(10)).unwrap_or(false)) {
+            while i < chars.len() && (chars[i].is_digit(10) || chars[i] == '.') { i += 1; }
+            let text: String = chars[start..i].iter().cloned().collect();
+            match text.parse::<f32>() {
+                Ok(value) => Token::Number(value),
+                Err(_) => return Err(ExpressionError { position: start, message: format!("invalid number {}", text) })
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') { i += 1; }
+            // Components of properties are picked with an index, e.g. `this.position[1]`
+            if i < chars.len() && chars[i] == '[' {
+                while i < chars.len() && chars[i] != ']' { i += 1; }
+                if i == chars.len() {
+                    return Err(ExpressionError { position: start, message: "missing ]".to_string() });
+                }
+                i += 1;
+            }
+            Token::Name(chars[start..i].iter().cloned().collect())
+        } else {
+            i += 1;
+            match c {
+                '+' | '-' | '*' | '/' | '%' | '^' => Token::Operator(c),
+                '(' => Token::Open,
+                ')' => Token::Close,
+                ',' => Token::Comma,
+                _ => return Err(ExpressionError { position: start, message: format!("unexpected {}", c) })
+            }
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+#[derive(PartialEq, Debug, Clone)]
+enum Expr {
+    Number(f32),
+    Time,
+    Property(NamedPropRef, usize),
+    Negate(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>)
+}
+
+/// Functions expressions can call, with the number of arguments they take.
+const FUNCTIONS: &'static [(&'static str, usize)] = &[
+    ("sin", 1), ("cos", 1), ("tan", 1), ("asin", 1), ("acos", 1), ("atan", 1), ("atan2", 2),
+    ("sqrt", 1), ("abs", 1), ("floor", 1), ("ceil", 1), ("round", 1), ("fract", 1), ("sign", 1),
+    ("exp", 1), ("ln", 1), ("pow", 2), ("min", 2), ("max", 2), ("clamp", 3), ("lerp", 3)
+];
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|&(_, ref token)| token)
+    }
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map(|&(position, _)| position).unwrap_or(self.end)
+    }
+    fn error<T>(&self, message: String) -> Result<T, ExpressionError> {
+        Err(ExpressionError { position: self.position(), message: message })
+    }
+    fn expect(&mut self, token: Token, name: &str) -> Result<(), ExpressionError> {
+        if self.peek() == Some(&token) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.error(format!("expected {}", name))
+        }
+    }
+
+    // sum := product (('+' | '-') product)*
+    fn sum(&mut self) -> Result<Expr, ExpressionError> {
+        let mut expr = try!(self.product());
+        loop {
+            let op = match self.peek() {
+                Some(&Token::Operator(op)) if op == '+' || op == '-' => op,
+                _ => return Ok(expr)
+            };
+            self.pos += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(try!(self.product())));
+        }
+    }
+    // product := unary (('*' | '/' | '%') unary)*
+    fn product(&mut self) -> Result<Expr, ExpressionError> {
+        let mut expr = try!(self.unary());
+        loop {
+            let op = match self.peek() {
+                Some(&Token::Operator(op)) if op == '*' || op == '/' || op == '%' => op,
+                _ => return Ok(expr)
+            };
+            self.pos += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(try!(self.unary())));
+        }
+    }
+    // unary := '-' unary | power
+    fn unary(&mut self) -> Result<Expr, ExpressionError> {
+        if self.peek() == Some(&Token::Operator('-')) {
+            self.pos += 1;
+            return Ok(Expr::Negate(Box::new(try!(self.unary()))));
+        }
+        self.power()
+    }
+    // power := atom ('^' unary)?
+    fn power(&mut self) -> Result<Expr, ExpressionError> {
+        let base = try!(self.atom());
+        if self.peek() == Some(&Token::Operator('^')) {
+            self.pos += 1;
+            return Ok(Expr::Binary('^', Box::new(base), Box::new(try!(self.unary()))));
+        }
+        Ok(base)
+    }
+    fn atom(&mut self) -> Result<Expr, ExpressionError> {
+        let position = self.position();
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => return self.error("unexpected end of expression".to_string())
+        };
+        self.pos += 1;
+        match token {
+            Token::Number(value) => Ok(Expr::Number(value)),
+            Token::Open => {
+                let expr = try!(self.sum());
+                try!(self.expect(Token::Close, ")"));
+                Ok(expr)
+            },
+            Token::Name(name) => {
+                if self.peek() == Some(&Token::Open) {
+                    self.pos += 1;
+                    return self.call(name, position);
+                }
+                match name.as_str() {
+                    "t" | "time" => Ok(Expr::Time),
+                    "pi" => Ok(Expr::Number(PI)),
+                    "e" => Ok(Expr::Number(E)),
+                    _ if name.contains('.') => property(&name, position),
+                    _ => Err(ExpressionError { position: position, message: format!("unknown name {}", name) })
+                }
+            },
+            _ => Err(ExpressionError { position: position, message: "expected a value".to_string() })
+        }
+    }
+    fn call(&mut self, name: String, position: usize) -> Result<Expr, ExpressionError> {
+        let arity = match FUNCTIONS.iter().find(|&&(function, _)| function == name) {
+            Some(&(_, arity)) => arity,
+            None => return Err(ExpressionError { position: position, message: format!("unknown function {}", name) })
+        };
+        let mut args = vec![];
+        if self.peek() != Some(&Token::Close) {
+            args.push(try!(self.sum()));
+            while self.peek() == Some(&Token::Comma) {
+                self.pos += 1;
+                args.push(try!(self.sum()));
+            }
+        }
+        try!(self.expect(Token::Close, ")"));
+        if args.len() != arity {
+            return Err(ExpressionError { position: position, message: format!("{} takes {} arguments, found {}", name, arity, args.len()) });
+        }
+        Ok(Expr::Call(name, args))
+    }
+}
+
+fn property(name: &str, position: usize) -> Result<Expr, ExpressionError> {
+    let (property, component) = match (name.rfind('['), name.ends_with("]")) {
+        (Some(open), true) => match name[open + 1..name.len() - 1].parse::<usize>() {
+            Ok(component) => (&name[..open], component),
+            Err(_) => return Err(ExpressionError { position: position, message: format!("invalid component in {}", name) })
+        },
+        _ => (name, 0)
+    };
+    match Pon::from_string(property).ok().and_then(|pon| pon.as_reference().ok().map(|p| p.clone())) {
+        Some(property) => Ok(Expr::Property(property, component)),
+        None => Err(ExpressionError { position: position, message: format!("{} is not a property reference", name) })
+    }
+}
+
+/// A math expression of the time `t` in seconds and of document properties, e.g.
+/// `sin(t * 2) * 0.5 + parent.height`. Properties with several components are read one
+/// component at a time, e.g. `this.position[1]`.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Expression {
+    pub source: String,
+    expr: Expr
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Expression, ExpressionError> {
+        let mut parser = Parser { tokens: try!(tokenize(source)), pos: 0, end: source.chars().count() };
+        let expr = try!(parser.sum());
+        if parser.pos < parser.tokens.len() {
+            return parser.error("expected an operator".to_string());
+        }
+        Ok(Expression { source: source.to_string(), expr: expr })
+    }
+    /// The value at `time`, or None if a property it reads has no value.
+    pub fn evaluate(&self, time: f32, properties: &PropertySource) -> Option<f32> {
+        evaluate(&self.expr, time, properties)
+    }
+    /// The properties the expression reads
+    pub fn properties(&self) -> Vec<NamedPropRef> {
+        let mut res = vec![];
+        collect_expr_properties(&self.expr, &mut res);
+        res
+    }
+}
+
+fn collect_expr_properties(expr: &Expr, res: &mut Vec<NamedPropRef>) {
+    match expr {
+        &Expr::Property(ref property, _) => if !res.contains(property) { res.push(property.clone()); },
+        &Expr::Negate(ref a) => collect_expr_properties(a, res),
+        &Expr::Binary(_, ref a, ref b) => {
+            collect_expr_properties(a, res);
+            collect_expr_properties(b, res);
+        },
+        &Expr::Call(_, ref args) => for arg in args { collect_expr_properties(arg, res); },
+        _ => {}
+    }
+}
+
+fn evaluate(expr: &Expr, time: f32, properties: &PropertySource) -> Option<f32> {
+    Some(match expr {
+        &Expr::Number(value) => value,
+        &Expr::Time => time,
+        &Expr::Property(ref property, component) => try_opt!(try_opt!(properties.property_value(property)).value.get(component).cloned()),
+        &Expr::Negate(ref a) => -try_opt!(evaluate(a, time, properties)),
+        &Expr::Binary(op, ref a, ref b) => {
+            let a = try_opt!(evaluate(a, time, properties));
+            let b = try_opt!(evaluate(b, time, properties));
+            match op {
+                '+' => a + b,
+                '-' => a - b,
+                '*' => a * b,
+                '/' => a / b,
+                '%' => a % b,
+                _ => a.powf(b)
+            }
+        },
+        &Expr::Call(ref name, ref args) => {
+            let mut values = vec![];
+            for arg in args {
+                values.push(try_opt!(evaluate(arg, time, properties)));
+            }
+            let v = |i: usize| values[i];
+            match name.as_str() {
+                "sin" => v(0).sin(),
+                "cos" => v(0).cos(),
+                "tan" => v(0).tan(),
+                "asin" => v(0).asin(),
+                "acos" => v(0).acos(),
+                "atan" => v(0).atan(),
+                "atan2" => v(0).atan2(v(1)),
+                "sqrt" => v(0).sqrt(),
+                "abs" => v(0).abs(),
+                "floor" => v(0).floor(),
+                "ceil" => v(0).ceil(),
+                "round" => v(0).round(),
+                "fract" => v(0) - v(0).floor(),
+                "sign" => if v(0) > 0.0 { 1.0 } else if v(0) < 0.0 { -1.0 } else { 0.0 },
+                "exp" => v(0).exp(),
+                "ln" => v(0).ln(),
+                "pow" => v(0).powf(v(1)),
+                "min" => v(0).min(v(1)),
+                "max" => v(0).max(v(1)),
+                "clamp" => v(0).max(v(1)).min(v(2)),
+                _ => v(0) + (v(1) - v(0)) * v(2)
+            }
+        }
+    })
+}
+
+/// Drives a property with one expression per component.
+#[derive(Debug)]
+pub struct ExpressionTrack {
+    pub property: NamedPropRef,
+    pub expressions: Vec<Expression>,
+    pub duration: Duration
+}
+
+impl Track for ExpressionTrack {
+    fn value_at(&self, time: Duration) -> Vec<(NamedPropRef, Animatable)> {
+        self.value_at_with(time, &NoProperties)
+    }
+    fn value_at_with(&self, time: Duration, properties: &PropertySource) -> Vec<(NamedPropRef, Animatable)> {
+        let time = time.num_milliseconds() as f32 / 1000.0;
+        let mut value = vec![];
+        for expression in &self.expressions {
+            match expression.evaluate(time, properties) {
+                Some(v) => value.push(v),
+                None => return vec![]
+            }
+        }
+        vec![(self.property.clone(), Animatable::new(value))]
+    }
+    fn duration(&self) -> Duration {
+        self.duration
+    }
+    fn properties(&self) -> Vec<NamedPropRef> {
+        vec![self.property.clone()]
+    }
+}
+
+impl ToPon for ExpressionTrack {
+    fn to_pon(&self) -> Pon {
+        let value = match self.expressions.len() {
+            1 => Pon::String(self.expressions[0].source.clone()),
+            _ => Pon::Array(self.expressions.iter().map(|e| Pon::String(e.source.clone())).collect())
+        };
+        typed_pon("expression", object_pon(vec![
+            ("property", Pon::Reference(self.property.clone())),
+            ("value", value),
+            ("duration", (self.duration.num_milliseconds() as f32 / 1000.0).to_pon())
+        ]))
+    }
+}
+
+impl Translatable<Expression> for Pon {
+    fn inner_translate(&self, context: &mut TranslateContext) -> Result<Expression, PonTranslateErr> {
+        let source = try!(self.translate::<String>(context));
+        Expression::parse(&source).map_err(|err| PonTranslateErr::InvalidValue { value: format!("{} in expression '{}'", err, source) })
+    }
+}
+
+impl Translatable<ExpressionTrack> for Pon {
+    fn inner_translate(&self, context: &mut TranslateContext) -> Result<ExpressionTrack, PonTranslateErr> {
+        self.as_typed(|&TypedPon { ref data, .. }| -> Result<ExpressionTrack, PonTranslateErr> {
+            let property: &NamedPropRef = try!(try!(data.field("property")).as_reference());
+            let expressions: PonAutoVec<Expression> = try!(data.field_as("value", context));
+            let duration: f32 = try!(data.field_as_or("duration", Duration::weeks(1).num_milliseconds() as f32 / 1000.0, context));
+            Ok(ExpressionTrack {
+                property: property.clone(),
+                expressions: expressions.0,
+                duration: Duration::milliseconds((duration * 1000.0) as i64)
+            })
+        })
+    }
+}
+
+
+#[cfg(test)]
+fn parent_size() -> NamedPropRef {
+    Pon::from_string("parent.size").unwrap().as_reference().unwrap().clone()
+}
+
+#[cfg(test)]
+struct TestProperties;
+
+#[cfg(test)]
+impl PropertySource for TestProperties {
+    fn property_value(&self, property: &NamedPropRef) -> Option<Animatable> {
+        if property == &parent_size() { Some(Animatable::new(vec![2.0, 3.0])) } else { None }
+    }
+}
+
+#[test]
+fn test_expression() {
+    let expression = Expression::parse("-2 ^ 2 + max(1, t) * parent.size[1] % 4 + sin(pi * t)").unwrap();
+    assert_eq!(expression.properties(), vec![parent_size()]);
+    assert_eq!(expression.evaluate(0.0, &TestProperties), Some(-4.0 + 3.0 % 4.0));
+    assert_eq!(expression.evaluate(0.0, &NoProperties), None);
+    assert_eq!(Expression::parse("t * (2 +").unwrap_err(), ExpressionError { position: 8, message: "unexpected end of expression".to_string() });
+    assert_eq!(Expression::parse("clamp(t, 1)").unwrap_err().message, "clamp takes 3 arguments, found 2".to_string());
+}
+
+#[test]
+fn test_expression_track() {
+    let track: ExpressionTrack = Pon::from_string("expression { property: this.x, value: ['t * 2', 'parent.size + 1'] }")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    assert_eq!(track.value_at_with(Duration::milliseconds(500), &TestProperties),
+        vec![(NamedPropRef::new(EntityPath::This, "x"), Animatable::new(vec![1.0, 3.0]))]);
+    assert_eq!(track.value_at(Duration::milliseconds(500)), vec![]);
+    let err: Result<ExpressionTrack, PonTranslateErr> = Pon::from_string("expression { property: this.x, value: 'sin(t' }")
+        .unwrap().translate(&mut TranslateContext::empty());
+    assert!(err.is_err());
+}
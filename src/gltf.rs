@@ -0,0 +1,484 @@
+
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::fs::File;
+use std::mem;
+use std::path::Path;
+use time::*;
+use rustc_serialize::json::Json;
+use rustc_serialize::base64::FromBase64;
+use pyramid::pon::*;
+use pyramid::document::*;
+use animatable::*;
+use curve::*;
+use curve_track::*;
+use track::*;
+use track_set::*;
+use import::*;
+use loader::*;
+
+const GLB_MAGIC: u32 = 0x46546C67;
+const GLB_CHUNK_JSON: u32 = 0x4E4F534A;
+const GLB_CHUNK_BIN: u32 = 0x004E4942;
+
+#[derive(Debug)]
+pub enum GltfError {
+    Io(io::Error),
+    Json(String),
+    Invalid(String)
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &GltfError::Io(ref err) => write!(f, "{}", err),
+            &GltfError::Json(ref err) => write!(f, "invalid json: {}", err),
+            &GltfError::Invalid(ref err) => write!(f, "invalid gltf: {}", err)
+        }
+    }
+}
+
+fn invalid<T>(message: String) -> Result<T, GltfError> {
+    Err(GltfError::Invalid(message))
+}
+
+fn field<'a>(json: &'a Json, name: &str) -> Result<&'a Json, GltfError> {
+    match json.find(name) {
+        Some(value) => Ok(value),
+        None => invalid(format!("missing field {}", name))
+    }
+}
+
+fn field_usize(json: &Json, name: &str) -> Result<usize, GltfError> {
+    match try!(field(json, name)).as_u64() {
+        Some(value) => Ok(value as usize),
+        None => invalid(format!("field {} is not an index", name))
+    }
+}
+
+fn field_usize_or(json: &Json, name: &str, default: usize) -> Result<usize, GltfError> {
+    match json.find(name) {
+        Some(_) => field_usize(json, name),
+        None => Ok(default)
+    }
+}
+
+fn array<'a>(json: &'a Json, name: &str) -> &'a [Json] {
+    match json.find(name).and_then(|value| value.as_array()) {
+        Some(arr) => arr,
+        None => &[]
+    }
+}
+
+fn element<'a>(json: &'a Json, name: &str, index: usize) -> Result<&'a Json, GltfError> {
+    match array(json, name).get(index) {
+        Some(value) => Ok(value),
+        None => invalid(format!("{}[{}] does not exist", name, index))
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    (bytes[offset] as u32) | ((bytes[offset + 1] as u32) << 8) | ((bytes[offset + 2] as u32) << 16) | ((bytes[offset + 3] as u32) << 24)
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    (bytes[offset] as u16) | ((bytes[offset + 1] as u16) << 8)
+}
+
+/// Splits a binary glTF container into its json and binary chunks.
+fn split_glb(bytes: &[u8]) -> Result<(String, Option<Vec<u8>>), GltfError> {
+    if bytes.len() < 12 {
+        return invalid("truncated glb header".to_string());
+    }
+    let length = read_u32(bytes, 8) as usize;
+    if length > bytes.len() {
+        return invalid("truncated glb file".to_string());
+    }
+    let mut json = None;
+    let mut bin = None;
+    let mut offset = 12;
+    while offset + 8 <= length {
+        let chunk_length = read_u32(bytes, offset) as usize;
+        let chunk_type = read_u32(bytes, offset + 4);
+        let start = offset + 8;
+        if start + chunk_length > length {
+            return invalid("truncated glb chunk".to_string());
+        }
+        let data = &bytes[start..start + chunk_length];
+        if chunk_type == GLB_CHUNK_JSON && json.is_none() {
+            json = match String::from_utf8(data.to_vec()) {
+                Ok(text) => Some(text),
+                Err(_) => return invalid("glb json chunk is not utf-8".to_string())
+            };
+        } else if chunk_type == GLB_CHUNK_BIN && bin.is_none() {
+            bin = Some(data.to_vec());
+        }
+        offset = start + chunk_length;
+    }
+    match json {
+        Some(json) => Ok((json, bin)),
+        None => invalid("glb file has no json chunk".to_string())
+    }
+}
+
+fn load_buffers(doc: &Json, base_dir: &Path, mut glb_bin: Option<Vec<u8>>) -> Result<Vec<Vec<u8>>, GltfError> {
+    let mut buffers = vec![];
+    for (i, buffer) in array(doc, "buffers").iter().enumerate() {
+        let data = match buffer.find("uri").and_then(|uri| uri.as_string()) {
+            Some(uri) if uri.starts_with("data:") => {
+                let encoded = match uri.find(',') {
+                    Some(comma) => &uri[comma + 1..],
+                    None => return invalid(format!("buffers[{}] has a malformed data uri", i))
+                };
+                match encoded.from_base64() {
+                    Ok(data) => data,
+                    Err(err) => return invalid(format!("buffers[{}]: {:?}", i, err))
+                }
+            },
+            Some(uri) => {
+                let mut data = vec![];
+                match File::open(base_dir.join(uri)).and_then(|mut file| file.read_to_end(&mut data)) {
+                    Ok(_) => data,
+                    Err(err) => return Err(GltfError::Io(err))
+                }
+            },
+            None if i == 0 && glb_bin.is_some() => glb_bin.take().unwrap(),
+            None => return invalid(format!("buffers[{}] has no data", i))
+        };
+        buffers.push(data);
+    }
+    Ok(buffers)
+}
+
+fn read_component(bytes: &[u8], offset: usize, component_type: usize, normalized: bool) -> f32 {
+    match component_type {
+        5126 => unsafe { mem::transmute::<u32, f32>(read_u32(bytes, offset)) },
+        5120 => {
+            let v = bytes[offset] as i8 as f32;
+            if normalized { (v / 127.0).max(-1.0) } else { v }
+        },
+        5121 => {
+            let v = bytes[offset] as f32;
+            if normalized { v / 255.0 } else { v }
+        },
+        5122 => {
+            let v = read_u16(bytes, offset) as i16 as f32;
+            if normalized { (v / 32767.0).max(-1.0) } else { v }
+        },
+        5123 => {
+            let v = read_u16(bytes, offset) as f32;
+            if normalized { v / 65535.0 } else { v }
+        },
+        _ => unreachable!()
+    }
+}
+
+/// Reads an accessor as a flat list of floats, `count * components` long.
+fn read_accessor(doc: &Json, buffers: &Vec<Vec<u8>>, index: usize) -> Result<Vec<f32>, GltfError> {
+    let accessor = try!(element(doc, "accessors", index));
+    let count = try!(field_usize(accessor, "count"));
+    let components = match try!(field(accessor, "type")).as_string() {
+        Some("SCALAR") => 1,
+        Some("VEC2") => 2,
+        Some("VEC3") => 3,
+        Some("VEC4") => 4,
+        t @ _ => return invalid(format!("accessors[{}] has unsupported type {:?}", index, t))
+    };
+    let component_type = try!(field_usize(accessor, "componentType"));
+    let component_size = match component_type {
+        5126 => 4,
+        5120 | 5121 => 1,
+        5122 | 5123 => 2,
+        t @ _ => return invalid(format!("accessors[{}] has unsupported component type {}", index, t))
+    };
+    let normalized = accessor.find("normalized").and_then(|n| n.as_boolean()).unwrap_or(false);
+    let view_index = match accessor.find("bufferView") {
+        Some(_) => try!(field_usize(accessor, "bufferView")),
+        None => return Ok(vec![0.0; count * components])
+    };
+    let view = try!(element(doc, "bufferViews", view_index));
+    let buffer = match buffers.get(try!(field_usize(view, "buffer"))) {
+        Some(buffer) => buffer,
+        None => return invalid(format!("bufferViews[{}] refers to a missing buffer", view_index))
+    };
+    let offset = try!(field_usize_or(view, "byteOffset", 0)) + try!(field_usize_or(accessor, "byteOffset", 0));
+    let stride = try!(field_usize_or(view, "byteStride", component_size * components));
+    if count > 0 && offset + (count - 1) * stride + components * component_size > buffer.len() {
+        return invalid(format!("accessors[{}] reads past the end of its buffer", index));
+    }
+    let mut values = Vec::with_capacity(count * components);
+    for i in 0..count {
+        for c in 0..components {
+            values.push(read_component(buffer, offset + i * stride + c * component_size, component_type, normalized));
+        }
+    }
+    Ok(values)
+}
+
+/// Reads a sampler into a curve and the time of its last key. Rotation keys are kept in the
+/// same hemisphere as the key before them, so interpolation takes the short way round.
+fn read_channel(doc: &Json, buffers: &Vec<Vec<u8>>, sampler: &Json, rotation: bool) -> Result<(Box<AnimationCurve>, f32), GltfError> {
+    let times = try!(read_accessor(doc, buffers, try!(field_usize(sampler, "input"))));
+    let values = try!(read_accessor(doc, buffers, try!(field_usize(sampler, "output"))));
+    let interpolation = sampler.find("interpolation").and_then(|i| i.as_string()).unwrap_or("LINEAR");
+    let elements = if interpolation == "CUBICSPLINE" { 3 } else { 1 };
+    if times.len() == 0 {
+        return invalid("animation sampler has no keys".to_string());
+    }
+    let width = values.len() / (times.len() * elements);
+    if width == 0 || width * times.len() * elements != values.len() {
+        return invalid(format!("animation sampler has {} output values for {} keys", values.len(), times.len()));
+    }
+    let mut outputs: Vec<Animatable> = (0..times.len() * elements)
+        .map(|i| Animatable::new(values[i * width..(i + 1) * width].to_vec())).collect();
+    if rotation && width == 4 {
+        let middle = elements / 2;
+        for i in 1..times.len() {
+            let dot = (0..4).map(|c| outputs[(i - 1) * elements + middle].value[c] * outputs[i * elements + middle].value[c])
+                .fold(0.0, |s, d| s + d);
+            if dot < 0.0 {
+                for output in outputs[i * elements..(i + 1) * elements].iter_mut() {
+                    for c in output.value.iter_mut() { *c = -*c; }
+                }
+            }
+        }
+    }
+    let value_at = |i: usize| outputs[i].clone();
+    let curve: Result<Box<AnimationCurve>, CurveError> = match interpolation {
+        "LINEAR" => LinearKeyFrameCurve::new((0..times.len()).map(|i| Key(times[i], value_at(i))).collect())
+            .map(|curve| Box::new(curve) as Box<AnimationCurve>),
+        "STEP" => StepKeyFrameCurve::new((0..times.len()).map(|i| Key(times[i], value_at(i))).collect())
+            .map(|curve| Box::new(curve) as Box<AnimationCurve>),
+        "CUBICSPLINE" => CubicSplineKeyFrameCurve::new((0..times.len()).map(|i| SplineKey {
+                time: times[i],
+                in_tangent: value_at(i * 3),
+                value: value_at(i * 3 + 1),
+                out_tangent: value_at(i * 3 + 2)
+            }).collect())
+            .map(|curve| Box::new(curve) as Box<AnimationCurve>),
+        i @ _ => return invalid(format!("unsupported interpolation {}", i))
+    };
+    let curve = match curve {
+        Ok(curve) => curve,
+        Err(err) => return invalid(format!("animation sampler: {}", err))
+    };
+    Ok((curve, times[times.len() - 1]))
+}
+
+fn read_animation(doc: &Json, buffers: &Vec<Vec<u8>>, animation: &Json, options: &ImportOptions) -> Result<TrackSet, GltfError> {
+    let samplers = array(animation, "samplers");
+    let mut curves = vec![];
+    let mut clip_duration = 0.0;
+    for channel in array(animation, "channels") {
+        let target = try!(field(channel, "target"));
+        let node_index = match target.find("node") {
+            Some(_) => try!(field_usize(target, "node")),
+            None => continue
+        };
+        let property_key = match try!(field(target, "path")).as_string() {
+            Some(path) if path == "translation" || path == "rotation" || path == "scale" || path == "weights" => path,
+            _ => continue
+        };
+        let node = try!(element(doc, "nodes", node_index));
+        let node_name = match node.find("name").and_then(|name| name.as_string()) {
+            Some(name) => name.to_string(),
+            None => format!("node_{}", node_index)
+        };
+        let sampler = match samplers.get(try!(field_usize(channel, "sampler"))) {
+            Some(sampler) => sampler,
+            None => return invalid(format!("channel on {} refers to a missing sampler", node_name))
+        };
+        let (curve, duration) = try!(read_channel(doc, buffers, sampler, property_key == "rotation"));
+        if duration > clip_duration {
+            clip_duration = duration;
+        }
+        curves.push((curve, NamedPropRef::new((options.entity_path)(&node_name), property_key)));
+    }
+    // Every track gets the length of the whole clip so looping clips stay in sync. A clip
+    // with a single key still lasts a millisecond, like a single frame BVH clip lasts a frame
+    let clip_duration = Duration::milliseconds(((clip_duration * 1000.0) as i64).max(1));
+    Ok(TrackSet {
+        tracks: curves.into_iter().map(|(curve, property)| -> Box<Track> {
+            Box::new(CurveTrack::new(property, curve, clip_duration, options.loop_type.clone()))
+        }).collect()
+    })
+}
+
+/// Reads every animation in a glTF 2.0 (json or binary) file into a track set, keyed by
+/// the animation name. Relative buffer uris are resolved against `base_dir`.
+pub fn parse_gltf(bytes: &[u8], base_dir: &Path, options: &ImportOptions) -> Result<Vec<(String, TrackSet)>, GltfError> {
+    let (text, glb_bin) = if bytes.len() >= 4 && read_u32(bytes, 0) == GLB_MAGIC {
+        try!(split_glb(bytes))
+    } else {
+        match String::from_utf8(bytes.to_vec()) {
+            Ok(text) => (text, None),
+            Err(_) => return invalid("file is neither glb nor utf-8 json".to_string())
+        }
+    };
+    let doc = match Json::from_str(&text) {
+        Ok(doc) => doc,
+        Err(err) => return Err(GltfError::Json(format!("{:?}", err)))
+    };
+    let buffers = try!(load_buffers(&doc, base_dir, glb_bin));
+    let mut clips = vec![];
+    for (i, animation) in array(&doc, "animations").iter().enumerate() {
+        let name = match animation.find("name").and_then(|name| name.as_string()) {
+            Some(name) => name.to_string(),
+            None => format!("animation_{}", i)
+        };
+        clips.push((name, try!(read_animation(&doc, &buffers, animation, options))));
+    }
+    Ok(clips)
+}
+
+/// Imports the animations of a glTF file and registers them as resources named
+/// `options.resource_prefix` followed by the animation name. Returns the resource names.
+pub fn load_gltf_animations(document: &mut Document, path: &Path, options: &ImportOptions) -> Result<Vec<String>, GltfError> {
+    let mut bytes = vec![];
+    match File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)) {
+        Ok(_) => {},
+        Err(err) => return Err(GltfError::Io(err))
+    };
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    let clips = try!(parse_gltf(&bytes, base_dir, options));
+    let mut names = vec![];
+    for (name, track_set) in clips {
+        let resource_id = format!("{}{}", options.resource_prefix, name);
+        register_track_set(document, &resource_id, track_set);
+        names.push(resource_id);
+    }
+    Ok(names)
+}
+
+
+#[cfg(test)]
+const TEST_GLTF: &'static str = r#"{
+    "asset": { "version": "2.0" },
+    "nodes": [ { "name": "arm" } ],
+    "buffers": [ { "byteLength": 32, "uri": "data:application/octet-stream;base64,AAAAAAAAgD8AAAAAAAAAAAAAAAAAAABAAACAQAAAwEA=" } ],
+    "bufferViews": [ { "buffer": 0, "byteLength": 8 }, { "buffer": 0, "byteOffset": 8, "byteLength": 24 } ],
+    "accessors": [
+        { "bufferView": 0, "componentType": 5126, "count": 2, "type": "SCALAR" },
+        { "bufferView": 1, "componentType": 5126, "count": 2, "type": "VEC3" }
+    ],
+    "animations": [ {
+        "name": "wave",
+        "samplers": [ { "input": 0, "output": 1 }, { "input": 0, "output": 1, "interpolation": "STEP" } ],
+        "channels": [
+            { "sampler": 0, "target": { "node": 0, "path": "translation" } },
+            { "sampler": 1, "target": { "node": 0, "path": "scale" } }
+        ]
+    } ]
+}"#;
+
+#[test]
+fn test_gltf_linear_and_step() {
+    let clips = parse_gltf(TEST_GLTF.as_bytes(), Path::new("."), &ImportOptions::new()).unwrap();
+    assert_eq!(clips.len(), 1);
+    assert_eq!(clips[0].0, "wave".to_string());
+    assert_eq!(clips[0].1.value_at(Duration::milliseconds(500)), vec![
+        (NamedPropRef::new(EntityPath::Named("arm".to_string()), "translation"), Animatable::new(vec![1.0, 2.0, 3.0])),
+        (NamedPropRef::new(EntityPath::Named("arm".to_string()), "scale"), Animatable::new(vec![0.0, 0.0, 0.0]))
+    ]);
+}
+
+#[test]
+fn test_gltf_entity_names() {
+    let mut names = ::std::collections::HashMap::new();
+    names.insert("arm".to_string(), "left_arm".to_string());
+    let clips = parse_gltf(TEST_GLTF.as_bytes(), Path::new("."), &ImportOptions::with_entity_names(names)).unwrap();
+    assert_eq!(clips[0].1.value_at(Duration::milliseconds(1000))[0],
+        (NamedPropRef::new(EntityPath::Named("left_arm".to_string()), "translation"), Animatable::new(vec![2.0, 4.0, 6.0])));
+}
+
+#[cfg(test)]
+const TEST_GLTF_TURN: &'static str = r#"{
+    "asset": { "version": "2.0" },
+    "nodes": [ { "name": "arm" } ],
+    "buffers": [ { "byteLength": 64, "uri": "data:application/octet-stream;base64,AAAAAAAAgD8AAKBAAAAAAAAAgD8AAAAAAACAPwAAoEAAAAAAAAAAAAAAAAAAAIA/AAAAAAAAAACamRm/zcxMvw==" } ],
+    "bufferViews": [ { "buffer": 0, "byteLength": 8 }, { "buffer": 0, "byteOffset": 8, "byteLength": 24 }, { "buffer": 0, "byteOffset": 32, "byteLength": 32 } ],
+    "accessors": [
+        { "bufferView": 0, "componentType": 5126, "count": 2, "type": "SCALAR" },
+        { "bufferView": 1, "componentType": 5126, "count": 6, "type": "SCALAR" },
+        { "bufferView": 2, "componentType": 5126, "count": 2, "type": "VEC4" }
+    ],
+    "animations": [ {
+        "name": "turn",
+        "samplers": [ { "input": 0, "output": 1, "interpolation": "CUBICSPLINE" }, { "input": 0, "output": 2 } ],
+        "channels": [
+            { "sampler": 0, "target": { "node": 0, "path": "weights" } },
+            { "sampler": 1, "target": { "node": 0, "path": "rotation" } }
+        ]
+    } ]
+}"#;
+
+#[test]
+fn test_gltf_cubic_spline() {
+    let clips = parse_gltf(TEST_GLTF_TURN.as_bytes(), Path::new("."), &ImportOptions::new()).unwrap();
+    // Key 0 leaves with a tangent of 1 and key 1 arrives flat; the outer tangents are unused
+    assert_eq!(clips[0].1.value_at(Duration::milliseconds(500))[0],
+        (NamedPropRef::new(EntityPath::Named("arm".to_string()), "weights"), Animatable::new_float(0.625)));
+}
+
+#[test]
+fn test_gltf_rotation_hemisphere() {
+    let clips = parse_gltf(TEST_GLTF_TURN.as_bytes(), Path::new("."), &ImportOptions::new()).unwrap();
+    let property = NamedPropRef::new(EntityPath::Named("arm".to_string()), "rotation");
+    // The second key is stored as [0, 0, -0.6, -0.8] and is flipped next to [0, 0, 0, 1]
+    assert_eq!(clips[0].1.value_at(Duration::milliseconds(1000))[1], (property.clone(), Animatable::new(vec![0.0, 0.0, 0.6, 0.8])));
+    let (_, halfway) = clips[0].1.value_at(Duration::milliseconds(500))[1].clone();
+    assert!(halfway.value[2] > 0.0 && halfway.value[3] > 0.8);
+}
+
+#[cfg(test)]
+fn test_glb(json: &str, bin: &[f32]) -> Vec<u8> {
+    fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+        for i in 0..4 {
+            bytes.push((value >> (i * 8)) as u8);
+        }
+    }
+    let mut json = json.as_bytes().to_vec();
+    while json.len() % 4 != 0 {
+        json.push(b' ');
+    }
+    let mut bytes = vec![];
+    push_u32(&mut bytes, GLB_MAGIC);
+    push_u32(&mut bytes, 2);
+    push_u32(&mut bytes, (12 + 8 + json.len() + 8 + bin.len() * 4) as u32);
+    push_u32(&mut bytes, json.len() as u32);
+    push_u32(&mut bytes, GLB_CHUNK_JSON);
+    bytes.extend(json.into_iter());
+    push_u32(&mut bytes, (bin.len() * 4) as u32);
+    push_u32(&mut bytes, GLB_CHUNK_BIN);
+    for value in bin {
+        push_u32(&mut bytes, unsafe { mem::transmute::<f32, u32>(*value) });
+    }
+    bytes
+}
+
+#[test]
+fn test_gltf_glb_single_key() {
+    let bytes = test_glb(r#"{
+        "asset": { "version": "2.0" },
+        "nodes": [ { "name": "arm" } ],
+        "buffers": [ { "byteLength": 16 } ],
+        "bufferViews": [ { "buffer": 0, "byteLength": 4 }, { "buffer": 0, "byteOffset": 4, "byteLength": 12 } ],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 1, "type": "SCALAR" },
+            { "bufferView": 1, "componentType": 5126, "count": 1, "type": "VEC3" }
+        ],
+        "animations": [ {
+            "name": "pose",
+            "samplers": [ { "input": 0, "output": 1 } ],
+            "channels": [ { "sampler": 0, "target": { "node": 0, "path": "translation" } } ]
+        } ]
+    }"#, &[0.0, 1.0, 2.0, 3.0]);
+    let options = ImportOptions { loop_type: Loop::Forever, ..ImportOptions::new() };
+    let clips = parse_gltf(&bytes, Path::new("."), &options).unwrap();
+    assert_eq!(clips[0].0, "pose".to_string());
+    assert_eq!(clips[0].1.duration(), Duration::milliseconds(1));
+    assert_eq!(clips[0].1.value_at(Duration::milliseconds(2500)), vec![
+        (NamedPropRef::new(EntityPath::Named("arm".to_string()), "translation"), Animatable::new(vec![1.0, 2.0, 3.0]))
+    ]);
+}
@@ -0,0 +1,31 @@
+
+use std::collections::HashMap;
+use pyramid::pon::*;
+use curve_track::*;
+
+/// Options shared by the importers for animation formats coming from other tools.
+pub struct ImportOptions {
+    /// Maps a node or joint name from the imported file to the entity its tracks drive
+    pub entity_path: Box<Fn(&str) -> EntityPath>,
+    /// Prepended to the clip names when the clips are registered as resources
+    pub resource_prefix: String,
+    pub loop_type: Loop
+}
+
+impl ImportOptions {
+    pub fn new() -> ImportOptions {
+        ImportOptions {
+            entity_path: Box::new(|name: &str| EntityPath::Named(name.to_string())),
+            resource_prefix: "".to_string(),
+            loop_type: Loop::Once
+        }
+    }
+    /// Targets the entities named in `names` instead of the names used in the file. Names
+    /// missing from the map are used as they are.
+    pub fn with_entity_names(names: HashMap<String, String>) -> ImportOptions {
+        ImportOptions {
+            entity_path: Box::new(move |name: &str| EntityPath::Named(names.get(name).cloned().unwrap_or(name.to_string()))),
+            ..ImportOptions::new()
+        }
+    }
+}
@@ -0,0 +1,157 @@
+
+use animatable::*;
+use curve::*;
+
+/// How far a reduced curve may be from the keys it drops.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Tolerance {
+    /// Euclidean distance between the values, for positions and scales
+    Distance(f32),
+    /// Angle in radians between the values read as [x, y, z, w] quaternions, for rotations
+    Angle(f32),
+    /// Largest difference in any single component
+    Component(f32)
+}
+
+impl Tolerance {
+    fn accepts(&self, a: &Animatable, b: &Animatable) -> bool {
+        let pairs = a.value.iter().zip(b.value.iter());
+        match self {
+            &Tolerance::Distance(max) => pairs.map(|(x, y)| (x - y) * (x - y)).fold(0.0, |s, d| s + d).sqrt() <= max,
+            &Tolerance::Angle(max) => {
+                let dot = pairs.map(|(x, y)| x * y).fold(0.0, |s, d| s + d);
+                let length = |v: &Animatable| v.value.iter().map(|x| x * x).fold(0.0, |s, x| s + x).sqrt();
+                let cos = (dot / (length(a) * length(b))).abs().min(1.0);
+                2.0 * cos.acos() <= max
+            },
+            &Tolerance::Component(max) => pairs.all(|(x, y)| (x - y).abs() <= max)
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct ReductionStats {
+    pub keys_before: usize,
+    pub keys_after: usize
+}
+
+impl ReductionStats {
+    /// Keys before reduction per key after it, e.g. 4.0 when three quarters of the keys were dropped
+    pub fn compression_ratio(&self) -> f32 {
+        if self.keys_after == 0 { 1.0 } else { self.keys_before as f32 / self.keys_after as f32 }
+    }
+    /// Combines the stats of reducing several curves
+    pub fn add(&self, other: &ReductionStats) -> ReductionStats {
+        ReductionStats {
+            keys_before: self.keys_before + other.keys_before,
+            keys_after: self.keys_after + other.keys_after
+        }
+    }
+}
+
+/// The indices of the keys to keep out of `count` keys, found by keeping the first key and
+/// then extending a segment from the last kept key for as long as `fits(from, to)` holds.
+fn keep_keys<F: Fn(usize, usize) -> bool>(count: usize, fits: F) -> Vec<usize> {
+    let mut kept = vec![0];
+    let mut anchor = 0;
+    let mut end = 1;
+    while end < count {
+        while end + 1 < count && fits(anchor, end + 1) {
+            end += 1;
+        }
+        kept.push(end);
+        anchor = end;
+        end += 1;
+    }
+    kept
+}
+
+/// Whether interpolating linearly from key `from` to key `to` stays within the tolerance of
+/// every key in between.
+fn segment_fits(keys: &[Key<Animatable>], from: usize, to: usize, tolerance: &Tolerance) -> bool {
+    let a = &keys[from];
+    let b = &keys[to];
+    ((from + 1)..to).all(|i| {
+        let p = if b.0 > a.0 { (keys[i].0 - a.0) / (b.0 - a.0) } else { 0.0 };
+        tolerance.accepts(&Interpolateable::interpolate(&a.1, &b.1, &p), &keys[i].1)
+    })
+}
+
+/// Removes the keys of a linear curve that can be interpolated from the keys around them.
+/// Every dropped key is within `tolerance` of the reduced curve, and the first and last
+/// keys are always kept.
+pub fn reduce_keys(curve: &LinearKeyFrameCurve<Animatable>, tolerance: &Tolerance) -> (LinearKeyFrameCurve<Animatable>, ReductionStats) {
+    let keys = curve.keys();
+    let kept: Vec<Key<Animatable>> = keep_keys(keys.len(), |from, to| segment_fits(keys, from, to, tolerance))
+        .into_iter().map(|i| Key(keys[i].0, keys[i].1.clone())).collect();
+    let stats = ReductionStats { keys_before: keys.len(), keys_after: kept.len() };
+    (LinearKeyFrameCurve::new(kept).expect("kept keys are a subset of a valid curve's keys"), stats)
+}
+
+/// Whether the Hermite segment from key `from` to key `to`, with their outer tangents, stays
+/// within the tolerance of the curve at every key in between and halfway between each pair.
+fn spline_segment_fits(curve: &CubicSplineKeyFrameCurve, from: usize, to: usize, tolerance: &Tolerance) -> bool {
+    let keys = curve.keys();
+    let segment = CubicSplineKeyFrameCurve::new(vec![keys[from].clone(), keys[to].clone()])
+        .expect("keys of a valid curve are in order");
+    let mut times = ((from + 1)..to).map(|i| keys[i].time).chain((from..to).map(|i| (keys[i].time + keys[i + 1].time) / 2.0));
+    times.all(|t| tolerance.accepts(&segment.value(t), &curve.value(t)))
+}
+
+/// Removes the keys of a cubic spline curve that the spline between the keys around them
+/// follows closely enough. The kept keys keep their tangents, and since tangents are in value
+/// per second they still fit when the keys between them are dropped.
+pub fn reduce_spline_keys(curve: &CubicSplineKeyFrameCurve, tolerance: &Tolerance) -> (CubicSplineKeyFrameCurve, ReductionStats) {
+    let keys = curve.keys();
+    let kept: Vec<SplineKey> = keep_keys(keys.len(), |from, to| spline_segment_fits(curve, from, to, tolerance))
+        .into_iter().map(|i| keys[i].clone()).collect();
+    let stats = ReductionStats { keys_before: keys.len(), keys_after: kept.len() };
+    (CubicSplineKeyFrameCurve::new(kept).expect("kept keys are a subset of a valid curve's keys"), stats)
+}
+
+
+#[test]
+fn test_reduce_linear_keys() {
+    let curve = LinearKeyFrameCurve::new(
+        (0..11).map(|i| Key(i as f32, Animatable::new(vec![i as f32, if i <= 5 { 0.0 } else { (i - 5) as f32 }]))).collect()
+    ).unwrap();
+    let (reduced, stats) = reduce_keys(&curve, &Tolerance::Distance(0.01));
+    assert_eq!(reduced.key_times(), vec![0.0, 5.0, 10.0]);
+    assert_eq!(stats, ReductionStats { keys_before: 11, keys_after: 3 });
+    for i in 0..11 {
+        let t = i as f32;
+        assert_eq!(reduced.value(t), curve.value(t));
+    }
+}
+
+#[test]
+fn test_reduce_rotation_keys() {
+    let rotation = |angle: f32| Animatable::new(vec![0.0, 0.0, (angle / 2.0).sin(), (angle / 2.0).cos()]);
+    let curve = LinearKeyFrameCurve::new(
+        vec![Key(0.0, rotation(0.0)), Key(1.0, rotation(0.1)), Key(2.0, rotation(0.2)), Key(3.0, rotation(1.5))]
+    ).unwrap();
+    let (reduced, stats) = reduce_keys(&curve, &Tolerance::Angle(0.01));
+    assert_eq!(reduced.key_times(), vec![0.0, 2.0, 3.0]);
+    assert_eq!(stats.compression_ratio(), 4.0 / 3.0);
+}
+
+#[test]
+fn test_reduce_spline_keys() {
+    let zero = Animatable::new_float(0.0);
+    let original = CubicSplineKeyFrameCurve::new(vec![
+        SplineKey { time: 0.0, value: zero.clone(), in_tangent: zero.clone(), out_tangent: Animatable::new_float(1.0) },
+        SplineKey { time: 4.0, value: Animatable::new_float(2.0), in_tangent: zero.clone(), out_tangent: zero.clone() }
+    ]).unwrap();
+    // Splitting the curve at keys with its exact tangents gives the same curve
+    let mut keys = vec![original.keys()[0].clone()];
+    for i in 1..4 {
+        let t = i as f32;
+        let velocity = original.velocity(t);
+        keys.push(SplineKey { time: t, value: original.value(t), in_tangent: velocity.clone(), out_tangent: velocity });
+    }
+    keys.push(original.keys()[1].clone());
+    let curve = CubicSplineKeyFrameCurve::new(keys).unwrap();
+    let (reduced, stats) = reduce_spline_keys(&curve, &Tolerance::Component(0.001));
+    assert_eq!(reduced.key_times(), vec![0.0, 4.0]);
+    assert_eq!(stats, ReductionStats { keys_before: 5, keys_after: 2 });
+}
@@ -0,0 +1,318 @@
+#[cfg(test)]
+use std::cmp::Ordering;
+#[cfg(test)]
+use curve_track::*;
+
+use std::collections::HashMap;
+use time::*;
+use track::*;
+use validate::*;
+use pyramid::pon::*;
+use pyramid::document::*;
+use animatable::*;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum LayerBlend {
+    /// Blends from the layers below towards this layer by `weight`, or from zero when no
+    /// layer below drives the property
+    Override,
+    /// Adds this layer, scaled by `weight`, on top of the layers below
+    Additive
+}
+
+/// Decides which properties a layer is allowed to drive. A property key of `*` in a
+/// pattern matches every property on that entity.
+#[derive(PartialEq, Debug, Clone)]
+pub struct PropertyMask {
+    pub include: Vec<NamedPropRef>,
+    pub exclude: Vec<NamedPropRef>
+}
+
+impl PropertyMask {
+    pub fn all() -> PropertyMask {
+        PropertyMask {
+            include: vec![],
+            exclude: vec![]
+        }
+    }
+    pub fn matches(&self, prop: &NamedPropRef) -> bool {
+        let included = self.include.len() == 0 || self.include.iter().any(|pattern| pattern_matches(pattern, prop));
+        included && !self.exclude.iter().any(|pattern| pattern_matches(pattern, prop))
+    }
+}
+
+fn pattern_matches(pattern: &NamedPropRef, prop: &NamedPropRef) -> bool {
+    pattern.entity_path == prop.entity_path && (pattern.property_key == "*" || pattern.property_key == prop.property_key)
+}
+
+#[derive(Debug)]
+pub struct AnimationLayer {
+    pub track: Box<Track>,
+    pub weight: f32,
+    pub blend: LayerBlend,
+    pub mask: PropertyMask,
+    pub priority: i32
+}
+
+/// A stack of layers evaluated from lowest to highest priority, each one blended on top
+/// of the result of the layers below it.
+#[derive(Debug)]
+pub struct LayerStack {
+    layers: Vec<AnimationLayer>
+}
+
+impl LayerStack {
+    pub fn new(mut layers: Vec<AnimationLayer>) -> LayerStack {
+        layers.sort_by(|a, b| a.priority.cmp(&b.priority));
+        LayerStack {
+            layers: layers
+        }
+    }
+    pub fn layers(&self) -> &Vec<AnimationLayer> {
+        &self.layers
+    }
+}
+
+impl Track for LayerStack {
+    fn value_at(&self, time: Duration) -> Vec<(NamedPropRef, Animatable)> {
+        self.value_at_with(time, &NoProperties)
+    }
+    fn value_at_with(&self, time: Duration, properties: &PropertySource) -> Vec<(NamedPropRef, Animatable)> {
+        let mut by_props: HashMap<NamedPropRef, Animatable> = HashMap::new();
+        for layer in &self.layers {
+            for (prop, value) in layer.track.value_at_with(time, properties) {
+                if !layer.mask.matches(&prop) { continue; }
+                let new_value = match by_props.get(&prop) {
+                    Some(base) => match layer.blend {
+                        LayerBlend::Override => Animatable::interpolate(base, &value, &layer.weight),
+                        LayerBlend::Additive => base.add_weighted(layer.weight, &value)
+                    },
+                    None => value.weighted(layer.weight)
+                };
+                by_props.insert(prop, new_value);
+            }
+        }
+        by_props.into_iter().collect()
+    }
+    fn duration(&self) -> Duration {
+        self.layers.iter().map(|layer| layer.track.duration()).max().unwrap_or(Duration::zero())
+    }
+    fn properties(&self) -> Vec<NamedPropRef> {
+        let mut res = vec![];
+        for layer in &self.layers {
+            for property in layer.track.properties() {
+                if layer.mask.matches(&property) && !res.contains(&property) {
+                    res.push(property);
+                }
+            }
+        }
+        res
+    }
+    fn key_times(&self) -> Vec<f32> {
+        self.layers.iter().flat_map(|layer| layer.track.key_times().into_iter()).collect()
+    }
+    fn advance(&mut self, time: Duration, delta: Duration, properties: &PropertySource) {
+        for layer in self.layers.iter_mut() {
+            layer.track.advance(time, delta, properties);
+        }
+    }
+    fn refresh_resources(&mut self, document: &Document) -> Vec<Diagnostic> {
+        self.layers.iter_mut().flat_map(|layer| layer.track.refresh_resources(document).into_iter()).collect()
+    }
+    fn is_stateful(&self) -> bool {
+        self.layers.iter().any(|layer| layer.track.is_stateful())
+    }
+}
+
+impl ToPon for LayerBlend {
+    fn to_pon(&self) -> Pon {
+        Pon::String(match self {
+            &LayerBlend::Override => "override",
+            &LayerBlend::Additive => "additive"
+        }.to_string())
+    }
+}
+
+impl ToPon for PropertyMask {
+    fn to_pon(&self) -> Pon {
+        object_pon(vec![
+            ("include", Pon::Array(self.include.iter().map(|p| Pon::Reference(p.clone())).collect())),
+            ("exclude", Pon::Array(self.exclude.iter().map(|p| Pon::Reference(p.clone())).collect()))
+        ])
+    }
+}
+
+impl ToPon for AnimationLayer {
+    fn to_pon(&self) -> Pon {
+        object_pon(vec![
+            ("track", self.track.to_pon()),
+            ("weight", self.weight.to_pon()),
+            ("blend", self.blend.to_pon()),
+            ("mask", self.mask.to_pon()),
+            ("priority", (self.priority as f32).to_pon())
+        ])
+    }
+}
+
+impl ToPon for LayerStack {
+    fn to_pon(&self) -> Pon {
+        typed_pon("layers", Pon::Array(self.layers.iter().map(|layer| layer.to_pon()).collect()))
+    }
+}
+
+fn translate_prop_refs(pon: &Pon) -> Result<Vec<NamedPropRef>, PonTranslateErr> {
+    match pon {
+        &Pon::Array(ref arr) => {
+            let mut res = vec![];
+            for p in arr {
+                res.push(try!(p.as_reference()).clone());
+            }
+            Ok(res)
+        },
+        _ => Ok(vec![try!(pon.as_reference()).clone()])
+    }
+}
+
+impl Translatable<LayerBlend> for Pon {
+    fn inner_translate(&self, context: &mut TranslateContext) -> Result<LayerBlend, PonTranslateErr> {
+        match try!(self.translate::<String>(context)).as_str() {
+            "override" => Ok(LayerBlend::Override),
+            "additive" => Ok(LayerBlend::Additive),
+            _ => Err(PonTranslateErr::InvalidValue { value: format!("{:?}", self) })
+        }
+    }
+}
+
+impl Translatable<PropertyMask> for Pon {
+    fn inner_translate(&self, _: &mut TranslateContext) -> Result<PropertyMask, PonTranslateErr> {
+        let include = match self.field("include") {
+            Ok(pon) => try!(translate_prop_refs(pon)),
+            Err(_) => vec![]
+        };
+        let exclude = match self.field("exclude") {
+            Ok(pon) => try!(translate_prop_refs(pon)),
+            Err(_) => vec![]
+        };
+        Ok(PropertyMask {
+            include: include,
+            exclude: exclude
+        })
+    }
+}
+
+impl Translatable<AnimationLayer> for Pon {
+    fn inner_translate(&self, context: &mut TranslateContext) -> Result<AnimationLayer, PonTranslateErr> {
+        // PON numbers are floats, so priorities are checked to be whole numbers
+        let priority: f32 = try!(self.field_as_or("priority", 0.0, context));
+        if priority.fract() != 0.0 || priority.abs() > ::std::i32::MAX as f32 {
+            return Err(PonTranslateErr::InvalidValue { value: format!("priority must be an integer, found {}", priority) });
+        }
+        Ok(AnimationLayer {
+            track: try!(self.field_as::<Box<Track>>("track", context)),
+            weight: try!(self.field_as_or("weight", 1.0, context)),
+            blend: try!(self.field_as_or("blend", LayerBlend::Override, context)),
+            mask: try!(self.field_as_or("mask", PropertyMask::all(), context)),
+            priority: priority as i32
+        })
+    }
+}
+
+impl Translatable<LayerStack> for Pon {
+    fn inner_translate(&self, context: &mut TranslateContext) -> Result<LayerStack, PonTranslateErr> {
+        self.as_typed(|&TypedPon { ref type_name, ref data }| {
+            match type_name.as_str() {
+                "layers" => {
+                    let layers = try!(data.translate::<PonAutoVec<AnimationLayer>>(context));
+                    Ok(LayerStack::new(layers.0))
+                },
+                s @ _ => Err(PonTranslateErr::UnrecognizedType(s.to_string()))
+            }
+        })
+    }
+}
+
+
+#[test]
+fn test_layers_override_with_mask() {
+    let stack = LayerStack::new(vec![
+        AnimationLayer {
+            track: Box::new(CurveTrack::new_fixed_value(NamedPropRef::new(EntityPath::This, "arm"), Animatable::new_float(1.0))),
+            weight: 1.0,
+            blend: LayerBlend::Override,
+            mask: PropertyMask { include: vec![NamedPropRef::new(EntityPath::This, "arm")], exclude: vec![] },
+            priority: 1
+        },
+        AnimationLayer {
+            track: Box::new(CurveTrack::new_fixed_value(NamedPropRef::new(EntityPath::This, "arm"), Animatable::new_float(3.0))),
+            weight: 1.0,
+            blend: LayerBlend::Override,
+            mask: PropertyMask::all(),
+            priority: 0
+        },
+        AnimationLayer {
+            track: Box::new(CurveTrack::new_fixed_value(NamedPropRef::new(EntityPath::This, "leg"), Animatable::new_float(5.0))),
+            weight: 0.5,
+            blend: LayerBlend::Override,
+            mask: PropertyMask { include: vec![NamedPropRef::new(EntityPath::This, "arm")], exclude: vec![] },
+            priority: 2
+        },
+    ]);
+    let mut values = stack.value_at(Duration::zero());
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    assert_eq!(values, vec![(NamedPropRef::new(EntityPath::This, "arm"), Animatable::new_float(1.0))]);
+}
+
+#[test]
+fn test_layers_additive_from_pon() {
+    let stack: LayerStack = Pon::from_string(
+        "layers [
+            { track: fixed_value { property: this.x, value: 2.0 } },
+            { track: fixed_value { property: this.x, value: 4.0 }, blend: 'additive', weight: 0.5, mask: { exclude: [this.y] } }
+        ]")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    assert_eq!(stack.value_at(Duration::zero()), vec![(NamedPropRef::new(EntityPath::This, "x"), Animatable::new_float(4.0))]);
+}
+
+#[test]
+fn test_layers_to_pon_roundtrip() {
+    let stack: LayerStack = Pon::from_string(
+        "layers [
+            { track: fixed_value { property: this.x, value: 2.0 }, priority: 3 },
+            { track: fixed_value { property: this.x, value: 4.0 }, blend: 'additive', mask: { include: [this.x] } }
+        ]")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    let pon = stack.to_pon();
+    let roundtrip: LayerStack = pon.translate(&mut TranslateContext::empty()).unwrap();
+    assert_eq!(roundtrip.to_pon(), pon);
+}
+
+#[test]
+fn test_layers_override_without_base() {
+    let stack: LayerStack = Pon::from_string(
+        "layers [ { track: fixed_value { property: this.x, value: 4.0 }, weight: 0.25 } ]")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    assert_eq!(stack.value_at(Duration::zero()), vec![(NamedPropRef::new(EntityPath::This, "x"), Animatable::new_float(1.0))]);
+    let res: Result<LayerStack, PonTranslateErr> = Pon::from_string(
+        "layers [ { track: fixed_value { property: this.x, value: 4.0 }, priority: 1.5 } ]")
+        .unwrap().translate(&mut TranslateContext::empty());
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_layers_additive_mask_without_base() {
+    let stack: LayerStack = Pon::from_string(
+        "layers [
+            { track: fixed_value { property: this.x, value: 2.0 } },
+            { track: track_set [ fixed_value { property: this.x, value: 4.0 }, key_framed { property: this.y, keys: [[0.0, 0.0], [1.0, 4.0]] } ],
+              blend: 'additive', weight: 0.5, mask: { include: [this.y] } }
+        ]")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    let mut values = stack.value_at(Duration::milliseconds(500));
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    // x is masked out of the additive layer, and y has no base so it is added to zero
+    assert_eq!(values, vec![
+        (NamedPropRef::new(EntityPath::This, "x"), Animatable::new_float(2.0)),
+        (NamedPropRef::new(EntityPath::This, "y"), Animatable::new_float(1.0))
+    ]);
+    assert_eq!(stack.key_times(), vec![0.0, 1.0]);
+}
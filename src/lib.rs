@@ -3,8 +3,10 @@
 extern crate pyramid;
 extern crate time;
 extern crate cgmath;
+extern crate rustc_serialize;
 
 use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::collections::hash_map::Entry;
 
 pub mod animatable;
@@ -13,6 +15,24 @@ pub mod curve_track;
 pub mod track_set;
 pub mod weighted_tracks;
 pub mod curve;
+pub mod layers;
+pub mod loader;
+pub mod import;
+pub mod gltf;
+pub mod bvh;
+pub mod binary_clip;
+pub mod csv;
+pub mod svg;
+pub mod validate;
+pub mod key_reduction;
+pub mod quantized_curve;
+pub mod oscillator;
+pub mod expression;
+pub mod spring;
+pub mod driven;
+pub mod time_remap;
+pub mod clip_range;
+pub mod path;
 
 use time::*;
 
@@ -26,58 +46,190 @@ pub use track_set::*;
 pub use curve_track::*;
 pub use curve::*;
 pub use animatable::*;
+pub use layers::*;
+pub use oscillator::*;
+pub use expression::*;
+pub use spring::*;
+pub use driven::*;
+pub use time_remap::*;
+pub use clip_range::*;
+pub use path::*;
+pub use loader::*;
+pub use import::*;
+pub use validate::*;
 
+/// Slot used for the plain `animation` property. It sorts before every named slot, so
+/// the entries of `animations` are blended on top of it.
+const DEFAULT_SLOT: &'static str = "";
+
+/// An animation playing in one slot. Every slot keeps its own start time, set when its
+/// animation is assigned, so assigning one slot restarts only that slot; there is no start
+/// time shared by the whole subsystem.
 struct EntityAnimation {
+    pon: Pon,
     track: Box<Track>,
+    start_time: Timespec,
+    /// The track time the track was last advanced to
+    advanced_to: Duration
+}
+
+impl EntityAnimation {
+    fn new(pon: Pon, track: Box<Track>) -> EntityAnimation {
+        EntityAnimation {
+            pon: pon,
+            track: track,
+            start_time: time::get_time(),
+            advanced_to: Duration::zero()
+        }
+    }
+}
+
+/// All animations playing on one entity. Slots are evaluated in name order, the default slot
+/// first, and a property written by several slots takes the value of the last one: slots
+/// override each other rather than blend. To blend animations, play a `layers` or
+/// `weighted_tracks` track in one slot.
+struct EntityAnimations {
+    slots: BTreeMap<String, EntityAnimation>,
     cached_resolved_named_prop_refs: HashMap<NamedPropRef, PropRef>
 }
 
 pub struct AnimationSubSystem {
-    animations: HashMap<EntityId, EntityAnimation>,
-    start_time: Timespec
+    animations: HashMap<EntityId, EntityAnimations>,
+    diagnostics: Vec<Diagnostic>
 }
 
 impl AnimationSubSystem {
     pub fn new() -> AnimationSubSystem {
         AnimationSubSystem {
             animations: HashMap::new(),
-            start_time: time::get_time()
+            diagnostics: vec![]
+        }
+    }
+    /// Returns the problems found with animations since the last call: animations that
+    /// failed to translate, with what the validator finds in them, and resources that
+    /// could not be reloaded. They are kept until taken.
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        ::std::mem::replace(&mut self.diagnostics, vec![])
+    }
+}
+
+/// Reads the properties tracks depend on from the document, relative to the animated entity.
+struct DocumentProperties<'a> {
+    document: &'a Document,
+    entity_id: &'a EntityId
+}
+
+impl<'a> PropertySource for DocumentProperties<'a> {
+    fn property_value(&self, property: &NamedPropRef) -> Option<Animatable> {
+        let prop_ref = match self.document.resolve_named_prop_ref(self.entity_id, property) {
+            Ok(prop_ref) => prop_ref,
+            Err(_) => return None
+        };
+        match self.document.get_property(&prop_ref.entity_id, &prop_ref.property_key) {
+            Ok(pon) => pon.translate::<Animatable>(&mut TranslateContext { document: Some(self.document) }).ok(),
+            Err(_) => None
+        }
+    }
+}
+
+fn slot_location(name: &str) -> String {
+    if name == DEFAULT_SLOT { "animation".to_string() } else { format!("animations.{}", name) }
+}
+
+fn set_slot(slots: &mut BTreeMap<String, EntityAnimation>, name: &str, pon: &Pon, document: &Document, entity_id: &EntityId, diagnostics: &mut Vec<Diagnostic>) {
+    match pon {
+        &Pon::Nil => { slots.remove(name); },
+        pn @ _ => {
+            match pn.translate::<Box<Track>>(&mut TranslateContext { document: Some(document) }) {
+                Ok(anim) => {
+                    slots.insert(name.to_string(), EntityAnimation::new(pn.clone(), anim));
+                },
+                Err(err) => {
+                    let location = slot_location(name);
+                    diagnostics.push(Diagnostic { location: location.clone(), message: err.to_string(), severity: Severity::Error });
+                    diagnostics.extend(validate_animation(pn, &location, Some((document, entity_id))).into_iter());
+                }
+            };
         }
+    };
+}
+
+fn set_named_slots(slots: &mut BTreeMap<String, EntityAnimation>, pon: &Pon, document: &Document, entity_id: &EntityId, diagnostics: &mut Vec<Diagnostic>) {
+    let stale: Vec<String> = match pon {
+        &Pon::Object(ref named) => slots.keys().filter(|name| name.as_str() != DEFAULT_SLOT && !named.contains_key(*name)).cloned().collect(),
+        _ => slots.keys().filter(|name| name.as_str() != DEFAULT_SLOT).cloned().collect()
+    };
+    for name in stale {
+        slots.remove(&name);
+    }
+    match pon {
+        &Pon::Object(ref named) => {
+            for (name, slot_pon) in named {
+                // Slots that did not change keep playing from where they are
+                if let Some(slot) = slots.get(name) {
+                    if &slot.pon == slot_pon { continue; }
+                }
+                set_slot(slots, name, slot_pon, document, entity_id, diagnostics);
+            }
+        },
+        &Pon::Nil => {},
+        _ => diagnostics.push(Diagnostic {
+            location: "animations".to_string(),
+            message: format!("expected an object of named animations, found {}", pon.to_string()),
+            severity: Severity::Error
+        })
     }
 }
 
+/// Advances every slot to `now` and returns the values the slots write, in the order
+/// described on `EntityAnimations`.
+fn evaluate_slots(slots: &mut BTreeMap<String, EntityAnimation>, now: Timespec, document: &Document, properties: &PropertySource,
+        diagnostics: &mut Vec<Diagnostic>) -> HashMap<NamedPropRef, Animatable> {
+    let mut values: HashMap<NamedPropRef, Animatable> = HashMap::new();
+    for (name, slot) in slots.iter_mut() {
+        // Playback time stays continuous across reloads since the slot keeps its start time
+        for diagnostic in slot.track.refresh_resources(document) {
+            diagnostics.push(Diagnostic { location: format!("{} resource {}", slot_location(name), diagnostic.location), ..diagnostic });
+        }
+        let time = now - slot.start_time;
+        slot.track.advance(time, time - slot.advanced_to, properties);
+        slot.advanced_to = time;
+        for (named_prop_ref, value) in slot.track.value_at_with(time, properties) {
+            values.insert(named_prop_ref, value);
+        }
+    }
+    values
+}
 
 impl ISubSystem for AnimationSubSystem {
 
     fn on_property_value_change(&mut self, system: &mut System, prop_refs: &Vec<PropRef>) {
         let doc = system.document_mut();
-        for pr in prop_refs.iter().filter(|pr| pr.property_key == "animation") {
+        for pr in prop_refs.iter().filter(|pr| pr.property_key == "animation" || pr.property_key == "animations") {
             let pon = &*doc.get_property(&pr.entity_id, &pr.property_key.as_str()).unwrap();
             pon.as_resolved(|pon| {
-                match pon {
-                    &Pon::Nil => {}, // Ignore nil pons
-                    pn @ _ => {
-                        match pn.translate::<Box<Track>>(&mut TranslateContext { document: Some(doc) }) {
-                            Ok(anim) => {
-                                self.animations.insert(pr.entity_id, EntityAnimation {
-                                    track: anim,
-                                    cached_resolved_named_prop_refs: HashMap::new()
-                                });
-                            },
-                            Err(err) => { println!("Failed to translate animation: {:?}", err.to_string()); }
-                        };
-                    }
-                };
+                let entity_animations = self.animations.entry(pr.entity_id).or_insert(EntityAnimations {
+                    slots: BTreeMap::new(),
+                    cached_resolved_named_prop_refs: HashMap::new()
+                });
+                if pr.property_key == "animation" {
+                    set_slot(&mut entity_animations.slots, DEFAULT_SLOT, pon, doc, &pr.entity_id, &mut self.diagnostics);
+                } else {
+                    set_named_slots(&mut entity_animations.slots, pon, doc, &pr.entity_id, &mut self.diagnostics);
+                }
                 Ok(())
             }).unwrap()
         }
     }
     fn update(&mut self, system: &mut System) {
-        let time = time::get_time() - self.start_time;
-        for (entity_id, entity_animation) in self.animations.iter_mut() {
-            let to_update = { entity_animation.track.value_at(time) };
+        let now = time::get_time();
+        for (entity_id, entity_animations) in self.animations.iter_mut() {
+            let to_update = {
+                let properties = DocumentProperties { document: system.document(), entity_id: entity_id };
+                evaluate_slots(&mut entity_animations.slots, now, system.document(), &properties, &mut self.diagnostics)
+            };
             for (named_prop_ref, value) in to_update {
-                let target = match entity_animation.cached_resolved_named_prop_refs.entry(named_prop_ref.clone()) {
+                let target = match entity_animations.cached_resolved_named_prop_refs.entry(named_prop_ref.clone()) {
                     Entry::Occupied(o) => o.into_mut(),
                     Entry::Vacant(v) => v.insert(system.document().resolve_named_prop_ref(entity_id, &named_prop_ref).unwrap())
                 };
@@ -86,3 +238,37 @@ impl ISubSystem for AnimationSubSystem {
         }
     }
 }
+
+
+#[cfg(test)]
+fn test_slot(source: &str, start_time: Timespec) -> EntityAnimation {
+    let pon = Pon::from_string(source).unwrap();
+    let track = pon.translate::<Box<Track>>(&mut TranslateContext::empty()).unwrap();
+    EntityAnimation { start_time: start_time, ..EntityAnimation::new(pon, track) }
+}
+
+#[test]
+fn test_slots_override_in_name_order() {
+    let now = time::get_time();
+    let mut slots = BTreeMap::new();
+    slots.insert("wave".to_string(), test_slot("fixed_value { property: this.x, value: 3.0 }", now));
+    slots.insert(DEFAULT_SLOT.to_string(), test_slot("track_set [ fixed_value { property: this.x, value: 1.0 }, fixed_value { property: this.y, value: 1.0 } ]", now));
+    slots.insert("nod".to_string(), test_slot("track_set [ fixed_value { property: this.x, value: 2.0 }, fixed_value { property: this.y, value: 2.0 } ]", now));
+    let mut diagnostics = vec![];
+    let values = evaluate_slots(&mut slots, now, &Document::new(), &NoProperties, &mut diagnostics);
+    // The default slot is overridden by both named slots, and "nod" by "wave"
+    assert_eq!(values.get(&NamedPropRef::new(EntityPath::This, "x")), Some(&Animatable::new_float(3.0)));
+    assert_eq!(values.get(&NamedPropRef::new(EntityPath::This, "y")), Some(&Animatable::new_float(2.0)));
+    assert_eq!(diagnostics, vec![]);
+}
+
+#[test]
+fn test_slots_keep_their_start_time() {
+    let now = time::get_time();
+    let mut slots = BTreeMap::new();
+    slots.insert(DEFAULT_SLOT.to_string(), test_slot("key_framed { property: this.x, keys: [[0.0, 0.0], [1.0, 1.0]] }", now - Duration::milliseconds(500)));
+    slots.insert("nod".to_string(), test_slot("key_framed { property: this.y, keys: [[0.0, 0.0], [1.0, 1.0]] }", now - Duration::milliseconds(250)));
+    let values = evaluate_slots(&mut slots, now, &Document::new(), &NoProperties, &mut vec![]);
+    assert_eq!(values.get(&NamedPropRef::new(EntityPath::This, "x")), Some(&Animatable::new_float(0.5)));
+    assert_eq!(values.get(&NamedPropRef::new(EntityPath::This, "y")), Some(&Animatable::new_float(0.25)));
+}
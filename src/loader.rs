@@ -0,0 +1,119 @@
+
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use pyramid::pon::*;
+use pyramid::document::*;
+use track::*;
+use track_set::*;
+
+#[derive(Debug)]
+pub enum AnimationLoadError {
+    Io { path: PathBuf, err: io::Error },
+    Parse { path: PathBuf, err: String },
+    Translate { path: PathBuf, clip: String, err: PonTranslateErr }
+}
+
+impl fmt::Display for AnimationLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &AnimationLoadError::Io { ref path, ref err } => write!(f, "{}: {}", path.display(), err),
+            &AnimationLoadError::Parse { ref path, ref err } => write!(f, "{}: failed to parse: {}", path.display(), err),
+            &AnimationLoadError::Translate { ref path, ref clip, ref err } => write!(f, "{}: clip {}: {}", path.display(), clip, err.to_string())
+        }
+    }
+}
+
+fn translate_clip(pon: &Pon, context: &mut TranslateContext) -> Result<TrackSet, PonTranslateErr> {
+    match pon.translate::<TrackSet>(context) {
+        Ok(track_set) => Ok(track_set),
+        Err(_) => Ok(TrackSet { tracks: vec![try!(pon.translate::<Box<Track>>(context))] })
+    }
+}
+
+/// Parses an animation library. The source is either an object of named clips, or a
+/// single track which becomes a clip named after the file. Clips are returned sorted by name.
+pub fn parse_animation_library(source: &str, path: &Path, context: &mut TranslateContext) -> Result<Vec<(String, TrackSet)>, AnimationLoadError> {
+    let pon = match Pon::from_string(source) {
+        Ok(pon) => pon,
+        Err(err) => return Err(AnimationLoadError::Parse { path: path.to_path_buf(), err: format!("{:?}", err) })
+    };
+    let mut clips = vec![];
+    match &pon {
+        &Pon::Object(ref named) => {
+            for (name, clip) in named {
+                match translate_clip(clip, context) {
+                    Ok(track_set) => clips.push((name.clone(), track_set)),
+                    Err(err) => return Err(AnimationLoadError::Translate { path: path.to_path_buf(), clip: name.clone(), err: err })
+                }
+            }
+        },
+        _ => {
+            let name = match path.file_stem() {
+                Some(stem) => stem.to_string_lossy().into_owned(),
+                None => path.to_string_lossy().into_owned()
+            };
+            match translate_clip(&pon, context) {
+                Ok(track_set) => clips.push((name, track_set)),
+                Err(err) => return Err(AnimationLoadError::Translate { path: path.to_path_buf(), clip: name, err: err })
+            }
+        }
+    }
+    clips.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(clips)
+}
+
+pub fn read_animation_library(document: &Document, path: &Path) -> Result<Vec<(String, TrackSet)>, AnimationLoadError> {
+    let mut source = String::new();
+    match File::open(path).and_then(|mut file| file.read_to_string(&mut source)) {
+        Ok(_) => {},
+        Err(err) => return Err(AnimationLoadError::Io { path: path.to_path_buf(), err: err })
+    };
+    parse_animation_library(&source, path, &mut TranslateContext { document: Some(document) })
+}
+
+/// Loads an animation library from disk and registers every clip in it as a resource,
+/// so it can be played with `track_set_from_resource 'clip_name'`. Returns the names of
+/// the registered clips. Loading the same file again replaces the resources, which
+/// tracks that are already playing pick up on their next update.
+pub fn load_animation_library(document: &mut Document, path: &Path) -> Result<Vec<String>, AnimationLoadError> {
+    let clips = try!(read_animation_library(document, path));
+    let mut names = vec![];
+    for (name, track_set) in clips {
+        register_track_set(document, &name, track_set);
+        names.push(name);
+    }
+    Ok(names)
+}
+
+pub fn register_track_set(document: &mut Document, resource_id: &str, track_set: TrackSet) {
+    document.resources.insert(resource_id.to_string(), Box::new(Rc::new(track_set)));
+}
+
+
+#[test]
+fn test_parse_animation_library() {
+    let clips = parse_animation_library(
+        "{ walk: track_set [ fixed_value { property: this.x, value: 0.5 } ], idle: fixed_value { property: this.y, value: 0.2 } }",
+        Path::new("character.pon"), &mut TranslateContext::empty()).unwrap();
+    assert_eq!(clips.iter().map(|c| c.0.clone()).collect::<Vec<String>>(), vec!["idle".to_string(), "walk".to_string()]);
+    assert_eq!(clips[0].1.tracks.len(), 1);
+}
+
+#[test]
+fn test_parse_animation_library_reports_clip() {
+    let err = parse_animation_library(
+        "{ walk: fixed_value { property: this.x, value: 0.5 }, broken: no_such_track {} }",
+        Path::new("character.pon"), &mut TranslateContext::empty()).unwrap_err();
+    assert!(err.to_string().starts_with("character.pon: clip broken:"));
+}
+
+#[test]
+fn test_parse_animation_library_resource_without_document() {
+    // animtool parses libraries without a document, so resources are reported rather than looked up
+    let err = parse_animation_library("{ walk: track_set_from_resource 'run' }", Path::new("character.pon"), &mut TranslateContext::empty()).unwrap_err();
+    assert!(err.to_string().starts_with("character.pon: clip walk:"));
+}
@@ -0,0 +1,164 @@
+
+use std::f32::consts::PI;
+use pyramid::pon::*;
+use animatable::*;
+use curve::*;
+use track::*;
+
+/// Names of the PON types of oscillator curves.
+pub const OSCILLATOR_TYPES: &'static [&'static str] = &["sine", "cosine", "triangle", "sawtooth", "square", "noise"];
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Waveform {
+    Sine,
+    Cosine,
+    /// Rises from 0 to 1 over the first quarter of a cycle, like a sine
+    Triangle,
+    /// Rises from -1 to 1 over each cycle
+    Sawtooth,
+    /// 1 for the first `duty` fraction of each cycle, -1 for the rest
+    Square { duty: f32 },
+    /// Smooth gradient noise between -1 and 1, with a random gradient at every cycle.
+    /// Each component gets its own noise, and the same seed always gives the same noise.
+    Noise { seed: u32 }
+}
+
+/// A periodic curve, `offset + amplitude * wave(frequency * time + phase)` for each component.
+/// Amplitude or offset with a single component apply to every component of the other.
+#[derive(PartialEq, Debug, Clone)]
+pub struct OscillatorCurve {
+    pub waveform: Waveform,
+    pub amplitude: Animatable,
+    /// Cycles per second
+    pub frequency: f32,
+    /// In cycles
+    pub phase: f32,
+    pub offset: Animatable
+}
+
+fn component(value: &Animatable, c: usize) -> f32 {
+    value.value.get(c).or(value.value.get(0)).cloned().unwrap_or(0.0)
+}
+
+fn lattice_gradient(seed: u32, i: i64) -> f32 {
+    let mut h = (i as u32).wrapping_mul(0x9E3779B1) ^ seed.wrapping_mul(0x85EBCA6B);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2C1B3C6D);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297A2D39);
+    h ^= h >> 15;
+    h as f32 / ::std::u32::MAX as f32 * 2.0 - 1.0
+}
+
+fn gradient_noise(seed: u32, x: f32) -> f32 {
+    let i = x.floor();
+    let f = x - i;
+    let fade = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+    let a = lattice_gradient(seed, i as i64) * f;
+    let b = lattice_gradient(seed, i as i64 + 1) * (f - 1.0);
+    // One dimensional gradient noise stays within -0.5..0.5
+    2.0 * (a + (b - a) * fade)
+}
+
+impl Waveform {
+    fn wave(&self, x: f32, c: usize) -> f32 {
+        let cycle = |x: f32| x - x.floor();
+        match self {
+            &Waveform::Sine => (2.0 * PI * x).sin(),
+            &Waveform::Cosine => (2.0 * PI * x).cos(),
+            &Waveform::Triangle => 1.0 - 4.0 * (cycle(x + 0.25) - 0.5).abs(),
+            &Waveform::Sawtooth => 2.0 * cycle(x) - 1.0,
+            &Waveform::Square { duty } => if cycle(x) < duty { 1.0 } else { -1.0 },
+            &Waveform::Noise { seed } => gradient_noise(seed ^ (c as u32).wrapping_mul(0x27D4EB2D), x)
+        }
+    }
+    fn type_name(&self) -> &'static str {
+        match self {
+            &Waveform::Sine => "sine",
+            &Waveform::Cosine => "cosine",
+            &Waveform::Triangle => "triangle",
+            &Waveform::Sawtooth => "sawtooth",
+            &Waveform::Square { .. } => "square",
+            &Waveform::Noise { .. } => "noise"
+        }
+    }
+}
+
+impl Curve<Animatable> for OscillatorCurve {
+    fn value(&self, time: f32) -> Animatable {
+        let x = self.frequency * time + self.phase;
+        let components = ::std::cmp::max(self.amplitude.value.len(), self.offset.value.len());
+        Animatable::new((0..components).map(|c| {
+            component(&self.offset, c) + component(&self.amplitude, c) * self.waveform.wave(x, c)
+        }).collect())
+    }
+}
+
+impl Differentiable for OscillatorCurve {}
+
+impl ToPon for OscillatorCurve {
+    fn to_pon(&self) -> Pon {
+        let mut fields = vec![
+            ("amplitude", self.amplitude.to_key_pon()),
+            ("frequency", self.frequency.to_pon()),
+            ("phase", self.phase.to_pon()),
+            ("offset", self.offset.to_key_pon())
+        ];
+        match self.waveform {
+            Waveform::Square { duty } => fields.push(("duty", duty.to_pon())),
+            Waveform::Noise { seed } => fields.push(("seed", (seed as f32).to_pon())),
+            _ => {}
+        }
+        typed_pon(self.waveform.type_name(), object_pon(fields))
+    }
+}
+
+impl Translatable<OscillatorCurve> for Pon {
+    fn inner_translate(&self, context: &mut TranslateContext) -> Result<OscillatorCurve, PonTranslateErr> {
+        self.as_typed(|&TypedPon { ref type_name, ref data }| -> Result<OscillatorCurve, PonTranslateErr> {
+            let waveform = match type_name.as_str() {
+                "sine" => Waveform::Sine,
+                "cosine" => Waveform::Cosine,
+                "triangle" => Waveform::Triangle,
+                "sawtooth" => Waveform::Sawtooth,
+                "square" => Waveform::Square { duty: try!(data.field_as_or("duty", 0.5, context)) },
+                "noise" => Waveform::Noise { seed: try!(data.field_as_or::<f32>("seed", 0.0, context)) as u32 },
+                s @ _ => return Err(PonTranslateErr::UnrecognizedType(s.to_string()))
+            };
+            Ok(OscillatorCurve {
+                waveform: waveform,
+                amplitude: try!(data.field_as_or("amplitude", Animatable::new_float(1.0), context)),
+                frequency: try!(data.field_as_or("frequency", 1.0, context)),
+                phase: try!(data.field_as_or("phase", 0.0, context)),
+                offset: try!(data.field_as_or("offset", Animatable::new_float(0.0), context))
+            })
+        })
+    }
+}
+
+
+#[test]
+fn test_oscillator_waves() {
+    let curve = |waveform: Waveform| OscillatorCurve {
+        waveform: waveform,
+        amplitude: Animatable::new(vec![1.0, 2.0]),
+        frequency: 0.5,
+        phase: 0.0,
+        offset: Animatable::new_float(1.0)
+    };
+    let square = curve(Waveform::Square { duty: 0.25 });
+    assert_eq!(square.value(0.4), Animatable::new(vec![2.0, 3.0]));
+    assert_eq!(square.value(0.6), Animatable::new(vec![0.0, -1.0]));
+    let triangle = curve(Waveform::Triangle);
+    assert_eq!(triangle.value(0.0), Animatable::new(vec![1.0, 1.0]));
+    assert_eq!(triangle.value(0.5), Animatable::new(vec![2.0, 3.0]));
+    assert_eq!(triangle.value(1.5), Animatable::new(vec![0.0, -1.0]));
+    assert_eq!(curve(Waveform::Sawtooth).value(1.0), Animatable::new(vec![1.0, 1.0]));
+    let noise = curve(Waveform::Noise { seed: 7 });
+    assert_eq!(noise.value(0.3), curve(Waveform::Noise { seed: 7 }).value(0.3));
+    assert!(noise.value(0.3) != curve(Waveform::Noise { seed: 8 }).value(0.3));
+    for i in 0..100 {
+        let value = noise.value(i as f32 * 0.37);
+        assert!(value.value[0] >= 0.0 && value.value[0] <= 2.0);
+    }
+}
@@ -0,0 +1,345 @@
+
+use time::*;
+use pyramid::pon::*;
+use animatable::*;
+use curve_track::*;
+use track::*;
+
+type Vec3 = [f32; 3];
+type Quaternion = [f32; 4];
+
+fn add(a: Vec3, b: Vec3) -> Vec3 { [a[0] + b[0], a[1] + b[1], a[2] + b[2]] }
+fn sub(a: Vec3, b: Vec3) -> Vec3 { [a[0] - b[0], a[1] - b[1], a[2] - b[2]] }
+fn scale(a: Vec3, s: f32) -> Vec3 { [a[0] * s, a[1] * s, a[2] * s] }
+fn dot(a: Vec3, b: Vec3) -> f32 { a[0] * b[0] + a[1] * b[1] + a[2] * b[2] }
+fn cross(a: Vec3, b: Vec3) -> Vec3 { [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]] }
+fn length(a: Vec3) -> f32 { dot(a, a).sqrt() }
+fn normalize(a: Vec3) -> Option<Vec3> {
+    let l = length(a);
+    if l > 0.000001 { Some(scale(a, 1.0 / l)) } else { None }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum PathKind {
+    /// Straight lines between the points
+    Linear,
+    /// A smooth curve through all the points
+    CatmullRom,
+    /// Cubic Bezier segments: a point, two control points, a point, two control points and so on
+    Bezier
+}
+
+impl PathKind {
+    fn name(&self) -> &'static str {
+        match self {
+            &PathKind::Linear => "linear",
+            &PathKind::CatmullRom => "catmull_rom",
+            &PathKind::Bezier => "bezier"
+        }
+    }
+}
+
+/// Samples per segment used to measure the length of the path
+const LENGTH_SAMPLES: usize = 32;
+
+/// A spline through 3D points that can be followed at constant speed.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Path {
+    pub kind: PathKind,
+    pub points: Vec<Vec3>,
+    /// Distance along the path at evenly spaced spline parameters, from 0 to `segments()`
+    lengths: Vec<f32>
+}
+
+impl Path {
+    pub fn new(kind: PathKind, points: Vec<Vec3>) -> Result<Path, String> {
+        let valid = match kind {
+            PathKind::Bezier => points.len() >= 4 && (points.len() - 1) % 3 == 0,
+            _ => points.len() >= 2
+        };
+        if !valid {
+            return Err(match kind {
+                PathKind::Bezier => format!("a bezier path needs 3n + 1 points, found {}", points.len()),
+                _ => format!("a path needs at least 2 points, found {}", points.len())
+            });
+        }
+        let mut path = Path { kind: kind, points: points, lengths: vec![0.0] };
+        let samples = path.segments() * LENGTH_SAMPLES;
+        let mut previous = path.point(0.0);
+        for i in 1..samples + 1 {
+            let point = path.point(i as f32 / LENGTH_SAMPLES as f32);
+            let total = path.lengths[i - 1] + length(sub(point, previous));
+            path.lengths.push(total);
+            previous = point;
+        }
+        Ok(path)
+    }
+
+    fn segments(&self) -> usize {
+        match self.kind {
+            PathKind::Bezier => (self.points.len() - 1) / 3,
+            _ => self.points.len() - 1
+        }
+    }
+
+    pub fn length(&self) -> f32 {
+        self.lengths[self.lengths.len() - 1]
+    }
+
+    /// The point at spline parameter `u`, where each segment spans one unit
+    fn point(&self, u: f32) -> Vec3 {
+        let segments = self.segments();
+        let u = u.max(0.0).min(segments as f32);
+        let i = if u as usize >= segments { segments - 1 } else { u as usize };
+        let t = u - i as f32;
+        let p = &self.points;
+        match self.kind {
+            PathKind::Linear => add(p[i], scale(sub(p[i + 1], p[i]), t)),
+            PathKind::CatmullRom => {
+                let p0 = if i > 0 { p[i - 1] } else { sub(scale(p[0], 2.0), p[1]) };
+                let p3 = if i + 2 < p.len() { p[i + 2] } else { sub(scale(p[i + 1], 2.0), p[i]) };
+                let (p1, p2) = (p[i], p[i + 1]);
+                let t2 = t * t;
+                let t3 = t2 * t;
+                scale(add(add(scale(p1, 2.0), scale(sub(p2, p0), t)),
+                    add(scale(add(sub(scale(p0, 2.0), scale(p1, 5.0)), sub(scale(p2, 4.0), p3)), t2),
+                        scale(add(sub(scale(p1, 3.0), p0), sub(p3, scale(p2, 3.0))), t3))), 0.5)
+            },
+            PathKind::Bezier => {
+                let (p0, p1, p2, p3) = (p[i * 3], p[i * 3 + 1], p[i * 3 + 2], p[i * 3 + 3]);
+                let s = 1.0 - t;
+                add(add(scale(p0, s * s * s), scale(p1, 3.0 * s * s * t)), add(scale(p2, 3.0 * s * t * t), scale(p3, t * t * t)))
+            }
+        }
+    }
+
+    /// The spline parameter at `distance` along the path
+    fn parameter_at(&self, distance: f32) -> f32 {
+        let last = self.lengths.len() - 1;
+        if distance <= 0.0 {
+            return 0.0;
+        } else if distance >= self.lengths[last] {
+            return last as f32 / LENGTH_SAMPLES as f32;
+        }
+        let (mut low, mut high) = (0, last);
+        while high - low > 1 {
+            let mid = (low + high) / 2;
+            if self.lengths[mid] > distance { high = mid; } else { low = mid; }
+        }
+        let span = self.lengths[high] - self.lengths[low];
+        let p = if span > 0.0 { (distance - self.lengths[low]) / span } else { 0.0 };
+        (low as f32 + p) / LENGTH_SAMPLES as f32
+    }
+
+    /// The point at `distance` along the path, and the direction the path goes there
+    pub fn at_distance(&self, distance: f32) -> (Vec3, Option<Vec3>) {
+        let u = self.parameter_at(distance);
+        let h = 0.5 / LENGTH_SAMPLES as f32;
+        let tangent = sub(self.point(u + h), self.point(u - h));
+        (self.point(u), normalize(tangent))
+    }
+}
+
+/// The rotation, as an [x, y, z, w] quaternion, that turns +z toward `forward` and +y as
+/// close to `up` as it can.
+fn look_rotation(forward: Vec3, up: Vec3) -> Quaternion {
+    let right = match normalize(cross(up, forward)) {
+        Some(right) => right,
+        // Facing straight along up, so any right angle to it will do
+        None => normalize(cross(if forward[0].abs() < 0.9 { [1.0, 0.0, 0.0] } else { [0.0, 0.0, 1.0] }, forward)).unwrap()
+    };
+    let up = cross(forward, right);
+    // Rotation matrix with right, up and forward as columns
+    let (m00, m01, m02) = (right[0], up[0], forward[0]);
+    let (m10, m11, m12) = (right[1], up[1], forward[1]);
+    let (m20, m21, m22) = (right[2], up[2], forward[2]);
+    let trace = m00 + m11 + m22;
+    if trace > 0.0 {
+        let s = (trace + 1.0).sqrt() * 2.0;
+        [(m21 - m12) / s, (m02 - m20) / s, (m10 - m01) / s, 0.25 * s]
+    } else if m00 > m11 && m00 > m22 {
+        let s = (1.0 + m00 - m11 - m22).sqrt() * 2.0;
+        [0.25 * s, (m01 + m10) / s, (m02 + m20) / s, (m21 - m12) / s]
+    } else if m11 > m22 {
+        let s = (1.0 + m11 - m00 - m22).sqrt() * 2.0;
+        [(m01 + m10) / s, 0.25 * s, (m12 + m21) / s, (m02 - m20) / s]
+    } else {
+        let s = (1.0 + m22 - m00 - m11).sqrt() * 2.0;
+        [(m02 + m20) / s, (m12 + m21) / s, 0.25 * s, (m10 - m01) / s]
+    }
+}
+
+/// The direction to face where the path has no direction, such as anywhere on a path of zero
+/// length: +z, or the closest direction at a right angle to `up`.
+fn rest_forward(up: Vec3) -> Vec3 {
+    normalize(cross([1.0, 0.0, 0.0], up)).unwrap_or([0.0, 0.0, 1.0])
+}
+
+/// Moves a position property along a path at constant speed, optionally turning a rotation
+/// property so +z faces along the path.
+#[derive(Debug)]
+pub struct PathTrack {
+    pub path: Path,
+    pub property: NamedPropRef,
+    /// Rotation property to drive, as an [x, y, z, w] quaternion
+    pub rotation: Option<NamedPropRef>,
+    pub up: Vec3,
+    pub duration: Duration,
+    pub loop_type: Loop
+}
+
+impl Track for PathTrack {
+    fn value_at(&self, time: Duration) -> Vec<(NamedPropRef, Animatable)> {
+        let time = if time > self.duration {
+            if self.loop_type == Loop::Forever && self.duration > Duration::zero() {
+                Duration::milliseconds(time.num_milliseconds() % self.duration.num_milliseconds())
+            } else {
+                return vec![]
+            }
+        } else {
+            time
+        };
+        let p = if self.duration > Duration::zero() {
+            time.num_milliseconds() as f32 / self.duration.num_milliseconds() as f32
+        } else {
+            1.0
+        };
+        let (position, tangent) = self.path.at_distance(p * self.path.length());
+        let mut res = vec![(self.property.clone(), Animatable::new(position.to_vec()))];
+        match (&self.rotation, tangent) {
+            (&Some(ref rotation), Some(tangent)) => res.push((rotation.clone(), Animatable::new(look_rotation(tangent, self.up).to_vec()))),
+            (&Some(ref rotation), None) => res.push((rotation.clone(), Animatable::new(look_rotation(rest_forward(self.up), self.up).to_vec()))),
+            (&None, _) => {}
+        }
+        res
+    }
+    fn duration(&self) -> Duration {
+        self.duration
+    }
+    fn properties(&self) -> Vec<NamedPropRef> {
+        let mut res = vec![self.property.clone()];
+        if let Some(ref rotation) = self.rotation {
+            res.push(rotation.clone());
+        }
+        res
+    }
+}
+
+fn vec3_pon(v: &Vec3) -> Pon {
+    Pon::FloatArray(v.to_vec())
+}
+
+impl ToPon for PathTrack {
+    fn to_pon(&self) -> Pon {
+        let mut fields = vec![
+            ("property", Pon::Reference(self.property.clone())),
+            ("points", Pon::Array(self.path.points.iter().map(vec3_pon).collect())),
+            ("interpolation", Pon::String(self.path.kind.name().to_string())),
+            ("up", vec3_pon(&self.up)),
+            ("duration", (self.duration.num_milliseconds() as f32 / 1000.0).to_pon()),
+            ("loop", self.loop_type.to_pon())
+        ];
+        if let Some(ref rotation) = self.rotation {
+            fields.push(("rotation", Pon::Reference(rotation.clone())));
+        }
+        typed_pon("path", object_pon(fields))
+    }
+}
+
+fn translate_vec3(pon: &Pon, context: &mut TranslateContext) -> Result<Vec3, PonTranslateErr> {
+    let value = try!(pon.translate::<Animatable>(context));
+    match value.value.len() {
+        3 => Ok([value.value[0], value.value[1], value.value[2]]),
+        n @ _ => Err(PonTranslateErr::InvalidValue { value: format!("expected a point with 3 components, found {}", n) })
+    }
+}
+
+impl Translatable<PathTrack> for Pon {
+    fn inner_translate(&self, context: &mut TranslateContext) -> Result<PathTrack, PonTranslateErr> {
+        self.as_typed(|&TypedPon { ref data, .. }| -> Result<PathTrack, PonTranslateErr> {
+            let property: &NamedPropRef = try!(try!(data.field("property")).as_reference());
+            let rotation = match data.field("rotation") {
+                Ok(rotation) => Some(try!(rotation.as_reference()).clone()),
+                Err(_) => None
+            };
+            let kind = match try!(data.field_as_or("interpolation", "catmull_rom".to_string(), context)).as_str() {
+                "linear" => PathKind::Linear,
+                "catmull_rom" => PathKind::CatmullRom,
+                "bezier" => PathKind::Bezier,
+                s @ _ => return Err(PonTranslateErr::InvalidValue { value: s.to_string() })
+            };
+            let mut points = vec![];
+            match try!(data.field("points")) {
+                &Pon::Array(ref arr) => for point in arr {
+                    points.push(try!(translate_vec3(point, context)));
+                },
+                pon @ _ => return Err(PonTranslateErr::InvalidValue { value: format!("points must be an array of points, found {}", pon.to_string()) })
+            }
+            let path = try!(Path::new(kind, points).map_err(|err| PonTranslateErr::InvalidValue { value: err }));
+            let up = match data.field("up") {
+                Ok(up) => try!(translate_vec3(up, context)),
+                Err(_) => [0.0, 1.0, 0.0]
+            };
+            if normalize(up).is_none() {
+                return Err(PonTranslateErr::InvalidValue { value: "up must not be a zero vector".to_string() });
+            }
+            let duration: f32 = try!(data.field_as_or("duration", 1.0, context));
+            if duration < 0.0 {
+                return Err(PonTranslateErr::InvalidValue { value: format!("duration must not be negative, found {}", duration) });
+            }
+            Ok(PathTrack {
+                path: path,
+                property: property.clone(),
+                rotation: rotation,
+                up: up,
+                duration: Duration::milliseconds((duration * 1000.0) as i64),
+                loop_type: try!(data.field_as_or("loop", Loop::Once, context))
+            })
+        })
+    }
+}
+
+
+#[test]
+fn test_path_constant_speed() {
+    let track: PathTrack = Pon::from_string(
+        "path { property: this.position, rotation: this.rotation, points: [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 0.0, 3.0]], interpolation: 'linear', duration: 4.0 }")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    assert!((track.path.length() - 4.0).abs() < 0.001);
+    let values = track.value_at(Duration::seconds(2));
+    let position = &values[0].1.value;
+    assert!(length(sub([position[0], position[1], position[2]], [1.0, 0.0, 1.0])) < 0.001);
+    // Facing +z along the second segment needs no rotation
+    let rotation = &values[1].1.value;
+    assert!(rotation[0].abs() < 0.001 && rotation[1].abs() < 0.001 && rotation[2].abs() < 0.001 && (rotation[3] - 1.0).abs() < 0.001);
+    let roundtrip: PathTrack = track.to_pon().translate(&mut TranslateContext::empty()).unwrap();
+    assert_eq!(roundtrip.to_pon(), track.to_pon());
+}
+
+#[test]
+fn test_path_catmull_rom_passes_through_points() {
+    let path = Path::new(PathKind::CatmullRom, vec![[0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [2.0, 0.0, 0.0]]).unwrap();
+    assert!(length(sub(path.point(1.0), [1.0, 1.0, 0.0])) < 0.001);
+    let (end, tangent) = path.at_distance(path.length());
+    assert!(length(sub(end, [2.0, 0.0, 0.0])) < 0.001);
+    assert!(tangent.unwrap()[0] > 0.0);
+    assert!(Path::new(PathKind::Bezier, vec![[0.0, 0.0, 0.0], [1.0, 1.0, 0.0]]).is_err());
+}
+
+#[test]
+fn test_path_degenerate() {
+    let track: PathTrack = Pon::from_string(
+        "path { property: this.position, rotation: this.rotation, points: [[1.0, 2.0, 3.0], [1.0, 2.0, 3.0]], interpolation: 'linear' }")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    let values = track.value_at(Duration::milliseconds(500));
+    assert_eq!(values.iter().map(|v| v.0.clone()).collect::<Vec<NamedPropRef>>(), track.properties());
+    assert_eq!(values[1].1, Animatable::new(vec![0.0, 0.0, 0.0, 1.0]));
+    for source in vec![
+        "path { property: this.position, points: 1.0 }",
+        "path { property: this.position, points: [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0]], up: [0.0, 0.0, 0.0] }",
+        "path { property: this.position, points: [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0]], duration: -1.0 }"
+    ] {
+        let res: Result<PathTrack, PonTranslateErr> = Pon::from_string(source).unwrap().translate(&mut TranslateContext::empty());
+        assert!(res.is_err());
+    }
+}
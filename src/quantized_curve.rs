@@ -0,0 +1,174 @@
+
+use std::rc::Rc;
+use pyramid::pon::*;
+use animatable::*;
+use curve::*;
+use curve_track::*;
+use track::*;
+
+/// How sample values are stored. Quantized values are stored relative to the range of each
+/// component over the whole curve or track.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Quantization {
+    Full,
+    Bits16,
+    Bits8
+}
+
+impl Quantization {
+    /// The largest quantized value
+    pub fn steps(&self) -> f32 {
+        match self {
+            &Quantization::Full => 0.0,
+            &Quantization::Bits16 => 65535.0,
+            &Quantization::Bits8 => 255.0
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+enum ChannelSamples {
+    Full(Vec<f32>),
+    Bits16(Vec<u16>),
+    Bits8(Vec<u8>)
+}
+
+/// The samples of one component, stored relative to the component's range.
+#[derive(PartialEq, Debug)]
+struct Channel {
+    min: f32,
+    max: f32,
+    samples: ChannelSamples
+}
+
+impl Channel {
+    fn new(values: Vec<f32>, quantization: Quantization) -> Channel {
+        let min = values.iter().cloned().fold(::std::f32::INFINITY, f32::min);
+        let max = values.iter().cloned().fold(::std::f32::NEG_INFINITY, f32::max);
+        let steps = quantization.steps();
+        let quantize = |v: &f32| if max > min { ((v - min) / (max - min) * steps).round() } else { 0.0 };
+        Channel {
+            min: min,
+            max: max,
+            samples: match quantization {
+                Quantization::Full => ChannelSamples::Full(values),
+                Quantization::Bits16 => ChannelSamples::Bits16(values.iter().map(|v| quantize(v) as u16).collect()),
+                Quantization::Bits8 => ChannelSamples::Bits8(values.iter().map(|v| quantize(v) as u8).collect())
+            }
+        }
+    }
+    fn sample(&self, index: usize) -> f32 {
+        let (q, steps) = match self.samples {
+            ChannelSamples::Full(ref values) => return values[index],
+            ChannelSamples::Bits16(ref values) => (values[index] as f32, Quantization::Bits16.steps()),
+            ChannelSamples::Bits8(ref values) => (values[index] as f32, Quantization::Bits8.steps())
+        };
+        self.min + q / steps * (self.max - self.min)
+    }
+    fn size(&self) -> usize {
+        match self.samples {
+            ChannelSamples::Full(ref values) => values.len() * 4,
+            ChannelSamples::Bits16(ref values) => values.len() * 2,
+            ChannelSamples::Bits8(ref values) => values.len()
+        }
+    }
+}
+
+/// A curve stored as uniformly spaced samples, which several curves can share the times of,
+/// with each component quantized to its own range and interpolated linearly between samples.
+/// Takes a fraction of the memory of a key framed curve with a key per frame, and can be
+/// played by a `CurveTrack` in place of one.
+#[derive(PartialEq, Debug)]
+pub struct QuantizedCurve {
+    pub times: Rc<SampleTimes>,
+    channels: Vec<Channel>
+}
+
+impl QuantizedCurve {
+    /// Samples `curve` at `times` and quantizes every component of the samples.
+    pub fn new<C: Curve<Animatable> + ?Sized>(curve: &C, times: Rc<SampleTimes>, quantization: Quantization) -> QuantizedCurve {
+        let samples: Vec<Animatable> = (0..times.count()).map(|i| curve.value(times.time(i))).collect();
+        let components = samples.iter().map(|s| s.value.len()).max().unwrap_or(0);
+        QuantizedCurve {
+            channels: (0..components).map(|c| {
+                Channel::new(samples.iter().map(|s| s.value.get(c).cloned().unwrap_or(0.0)).collect(), quantization)
+            }).collect(),
+            times: times
+        }
+    }
+    fn sample(&self, index: usize) -> Animatable {
+        Animatable::new(self.channels.iter().map(|channel| channel.sample(index)).collect())
+    }
+    /// Bytes used by the samples, not counting the shared sample times
+    pub fn data_size(&self) -> usize {
+        self.channels.iter().map(|channel| channel.size() + 8).fold(0, |a, b| a + b)
+    }
+}
+
+impl Curve<Animatable> for QuantizedCurve {
+    fn value(&self, time: f32) -> Animatable {
+        let last = self.times.count() - 1;
+        let p = if self.times.interval() > 0.0 { (time - self.times.start()) / self.times.interval() } else { 0.0 };
+        if p <= 0.0 {
+            return self.sample(0);
+        } else if p >= last as f32 {
+            return self.sample(last);
+        }
+        let i = p.floor() as usize;
+        Interpolateable::interpolate(&self.sample(i), &self.sample(i + 1), &(p - i as f32))
+    }
+    fn key_times(&self) -> Vec<f32> {
+        (0..self.times.count()).map(|i| self.times.time(i)).collect()
+    }
+}
+
+impl Differentiable for QuantizedCurve {}
+
+/// Written as a linear key framed curve with a key per sample, so it reads back uncompressed.
+impl ToPon for QuantizedCurve {
+    fn to_pon(&self) -> Pon {
+        LinearKeyFrameCurve::new((0..self.times.count()).map(|i| Key(self.times.time(i), self.sample(i))).collect())
+            .expect("sample times are in order")
+            .to_pon()
+    }
+}
+
+/// Replaces the curve of a track with a quantized curve sampled `sample_rate` times per second
+/// over the track's duration.
+pub fn quantize_curve_track(track: &mut CurveTrack, sample_rate: f32, quantization: Quantization) {
+    let times = match track.curve_time {
+        CurveTime::Absolute => SampleTimes::new(0.0, track.duration.num_milliseconds() as f32 / 1000.0, sample_rate),
+        CurveTime::Relative => SampleTimes::new(0.0, 1.0, sample_rate * track.duration.num_milliseconds() as f32 / 1000.0)
+    };
+    let curve = QuantizedCurve::new(&*track.curve, Rc::new(times), quantization);
+    track.curve = Box::new(curve);
+}
+
+
+#[test]
+fn test_quantized_curve() {
+    let curve = LinearKeyFrameCurve::new(
+        vec![Key(0.0, Animatable::new(vec![0.0, 10.0])), Key(1.0, Animatable::new(vec![1.0, 10.0])), Key(2.0, Animatable::new(vec![0.0, 10.0]))]
+    ).unwrap();
+    let times = Rc::new(SampleTimes::new(0.0, 2.0, 4.0));
+    let full = QuantizedCurve::new(&curve, times.clone(), Quantization::Full);
+    let quantized = QuantizedCurve::new(&curve, times.clone(), Quantization::Bits8);
+    assert_eq!(times.count(), 9);
+    assert_eq!(quantized.data_size(), 2 * (9 + 8));
+    for i in 0..21 {
+        let t = i as f32 * 0.1;
+        let (a, b, c) = (full.value(t), quantized.value(t), curve.value(t));
+        assert!((a.value[0] - c.value[0]).abs() < 0.0001);
+        assert!((b.value[0] - c.value[0]).abs() <= 0.5 / 255.0 + 0.0001);
+        assert_eq!(b.value[1], 10.0);
+    }
+}
+
+#[test]
+fn test_quantized_curve_single_sample() {
+    let curve = LinearKeyFrameCurve::new(vec![Key(0.0, Animatable::new_float(3.0))]).unwrap();
+    let times = Rc::new(SampleTimes::with_count(0.0, 1.0, 0));
+    assert_eq!(times.count(), 1);
+    let quantized = QuantizedCurve::new(&curve, times, Quantization::Bits16);
+    assert_eq!(quantized.value(0.5), Animatable::new_float(3.0));
+}
@@ -0,0 +1,217 @@
+
+use time::*;
+use pyramid::pon::*;
+use animatable::*;
+use track::*;
+
+/// What a spring pulls its property toward.
+#[derive(PartialEq, Debug, Clone)]
+pub enum SpringTarget {
+    Value(Animatable),
+    Property(NamedPropRef)
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum SpringMotion {
+    /// A damped spring, which overshoots the target unless damped enough
+    Spring { stiffness: f32, damping: f32, mass: f32 },
+    /// Critically damped smoothing, which reaches the target in roughly `smooth_time` seconds
+    /// without overshooting
+    Smooth { smooth_time: f32 }
+}
+
+/// Longest step the spring is integrated with, so stiff springs stay stable on slow frames
+const MAX_STEP: f32 = 1.0 / 120.0;
+
+/// Moves a property toward a target, keeping the position and velocity between updates. The
+/// value only changes when the track is advanced, so `value_at` ignores the time it is given.
+#[derive(Debug)]
+pub struct SpringTrack {
+    pub property: NamedPropRef,
+    pub target: SpringTarget,
+    pub motion: SpringMotion,
+    /// Where the property starts; when not given, it starts at its value when the track
+    /// is first advanced
+    pub from: Option<Animatable>,
+    position: Option<Vec<f32>>,
+    velocity: Vec<f32>
+}
+
+impl SpringTrack {
+    pub fn new(property: NamedPropRef, target: SpringTarget, motion: SpringMotion) -> SpringTrack {
+        SpringTrack {
+            property: property,
+            target: target,
+            motion: motion,
+            from: None,
+            position: None,
+            velocity: vec![]
+        }
+    }
+
+    fn step(&mut self, target: &Vec<f32>, dt: f32) {
+        let position = match self.position {
+            Some(ref mut position) => position,
+            None => return
+        };
+        for c in 0..position.len() {
+            let goal = target.get(c).cloned().unwrap_or(position[c]);
+            let (x, v) = (position[c], self.velocity[c]);
+            let (x, v) = match self.motion {
+                SpringMotion::Spring { stiffness, damping, mass } => {
+                    let a = (-stiffness * (x - goal) - damping * v) / mass;
+                    let v = v + a * dt;
+                    (x + v * dt, v)
+                },
+                SpringMotion::Smooth { smooth_time } => {
+                    let omega = 2.0 / smooth_time.max(0.0001);
+                    let o = omega * dt;
+                    let decay = 1.0 / (1.0 + o + 0.48 * o * o + 0.235 * o * o * o);
+                    let change = x - goal;
+                    let temp = (v + omega * change) * dt;
+                    (goal + (change + temp) * decay, (v - omega * temp) * decay)
+                }
+            };
+            position[c] = x;
+            self.velocity[c] = v;
+        }
+    }
+}
+
+impl Track for SpringTrack {
+    fn value_at(&self, _time: Duration) -> Vec<(NamedPropRef, Animatable)> {
+        match self.position {
+            Some(ref position) => vec![(self.property.clone(), Animatable::new(position.clone()))],
+            None => vec![]
+        }
+    }
+    fn is_stateful(&self) -> bool {
+        true
+    }
+    fn advance(&mut self, _time: Duration, delta: Duration, properties: &PropertySource) {
+        let target = match self.target {
+            SpringTarget::Value(ref value) => Some(value.clone()),
+            SpringTarget::Property(ref property) => properties.property_value(property)
+        };
+        let target = match target {
+            Some(target) => target.value,
+            None => return
+        };
+        if self.position.is_none() {
+            let start = match self.from {
+                Some(ref from) => from.clone(),
+                None => properties.property_value(&self.property).unwrap_or(Animatable::new(target.clone()))
+            };
+            self.velocity = start.value.iter().map(|_| 0.0).collect();
+            self.position = Some(start.value);
+        }
+        let mut remaining = delta.num_milliseconds() as f32 / 1000.0;
+        while remaining > 0.0 {
+            let dt = remaining.min(MAX_STEP);
+            self.step(&target, dt);
+            remaining -= dt;
+        }
+    }
+    fn duration(&self) -> Duration {
+        Duration::weeks(1)
+    }
+    fn properties(&self) -> Vec<NamedPropRef> {
+        vec![self.property.clone()]
+    }
+}
+
+impl ToPon for SpringTrack {
+    fn to_pon(&self) -> Pon {
+        let mut fields = vec![
+            ("property", Pon::Reference(self.property.clone())),
+            ("target", match self.target {
+                SpringTarget::Value(ref value) => value.to_key_pon(),
+                SpringTarget::Property(ref property) => Pon::Reference(property.clone())
+            })
+        ];
+        if let Some(ref from) = self.from {
+            fields.push(("from", from.to_key_pon()));
+        }
+        let type_name = match self.motion {
+            SpringMotion::Spring { stiffness, damping, mass } => {
+                fields.push(("stiffness", stiffness.to_pon()));
+                fields.push(("damping", damping.to_pon()));
+                fields.push(("mass", mass.to_pon()));
+                "spring"
+            },
+            SpringMotion::Smooth { smooth_time } => {
+                fields.push(("smooth_time", smooth_time.to_pon()));
+                "smooth_follow"
+            }
+        };
+        typed_pon(type_name, object_pon(fields))
+    }
+}
+
+impl Translatable<SpringTarget> for Pon {
+    fn inner_translate(&self, context: &mut TranslateContext) -> Result<SpringTarget, PonTranslateErr> {
+        match self {
+            &Pon::Reference(ref property) => Ok(SpringTarget::Property(property.clone())),
+            _ => Ok(SpringTarget::Value(try!(self.translate::<Animatable>(context))))
+        }
+    }
+}
+
+impl Translatable<SpringTrack> for Pon {
+    fn inner_translate(&self, context: &mut TranslateContext) -> Result<SpringTrack, PonTranslateErr> {
+        self.as_typed(|&TypedPon { ref type_name, ref data }| -> Result<SpringTrack, PonTranslateErr> {
+            let property: &NamedPropRef = try!(try!(data.field("property")).as_reference());
+            let target = try!(data.field_as::<SpringTarget>("target", context));
+            let motion = match type_name.as_str() {
+                "spring" => {
+                    let mass: f32 = try!(data.field_as_or("mass", 1.0, context));
+                    if mass <= 0.0 {
+                        return Err(PonTranslateErr::InvalidValue { value: format!("mass must be positive, found {}", mass) });
+                    }
+                    SpringMotion::Spring {
+                        stiffness: try!(data.field_as_or("stiffness", 100.0, context)),
+                        damping: try!(data.field_as_or("damping", 10.0, context)),
+                        mass: mass
+                    }
+                },
+                "smooth_follow" => SpringMotion::Smooth {
+                    smooth_time: try!(data.field_as_or("smooth_time", 0.3, context))
+                },
+                s @ _ => return Err(PonTranslateErr::UnrecognizedType(s.to_string()))
+            };
+            let mut track = SpringTrack::new(property.clone(), target, motion);
+            if let Ok(from) = data.field("from") {
+                track.from = Some(try!(from.translate::<Animatable>(context)));
+            }
+            Ok(track)
+        })
+    }
+}
+
+
+#[test]
+fn test_spring_settles() {
+    for source in vec![
+        "spring { property: this.x, target: [1.0, 2.0], from: [0.0, 0.0], stiffness: 50.0, damping: 14.0, mass: 1.0 }",
+        "smooth_follow { property: this.x, target: [1.0, 2.0], from: [0.0, 0.0], smooth_time: 0.2 }"
+    ] {
+        let mut track: SpringTrack = Pon::from_string(source).unwrap().translate(&mut TranslateContext::empty()).unwrap();
+        assert_eq!(track.value_at(Duration::zero()), vec![]);
+        track.advance(Duration::milliseconds(16), Duration::milliseconds(16), &NoProperties);
+        let first = track.value_at(Duration::zero())[0].1.clone();
+        assert!(first.value[0] > 0.0 && first.value[0] < 0.5);
+        for i in 2..300 {
+            track.advance(Duration::milliseconds(16 * i), Duration::milliseconds(16), &NoProperties);
+        }
+        let settled = track.value_at(Duration::zero())[0].1.clone();
+        assert!((settled.value[0] - 1.0).abs() < 0.01 && (settled.value[1] - 2.0).abs() < 0.01);
+        assert_eq!(Pon::from_string(source).unwrap(), track.to_pon());
+    }
+}
+
+#[test]
+fn test_spring_invalid_mass() {
+    let res: Result<SpringTrack, PonTranslateErr> = Pon::from_string("spring { property: this.x, target: 1.0, mass: 0.0 }")
+        .unwrap().translate(&mut TranslateContext::empty());
+    assert!(res.is_err());
+}
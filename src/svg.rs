@@ -0,0 +1,258 @@
+
+use time::*;
+use pyramid::pon::*;
+use animatable::*;
+use curve::*;
+use track::*;
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct PlotOptions {
+    pub width: f32,
+    /// Height of the plot of each component
+    pub panel_height: f32,
+    /// Number of points the plotted range is sampled at
+    pub samples: usize
+}
+
+impl PlotOptions {
+    pub fn new() -> PlotOptions {
+        PlotOptions {
+            width: 600.0,
+            panel_height: 150.0,
+            samples: 200
+        }
+    }
+}
+
+const MARGIN: f32 = 30.0;
+
+/// One plotted component: the sampled values, and the keys with their incoming and outgoing slopes.
+struct Panel {
+    label: String,
+    points: Vec<(f32, f32)>,
+    keys: Vec<(f32, f32, f32, f32)>
+}
+
+fn sample_times(start: f32, end: f32, samples: usize) -> Vec<f32> {
+    let samples = if samples < 2 { 2 } else { samples };
+    (0..samples).map(|i| start + (end - start) * i as f32 / (samples - 1) as f32).collect()
+}
+
+/// Escapes text so it can be written inside an xml element or attribute.
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+fn component(value: &Animatable, c: usize) -> f32 {
+    value.value.get(c).cloned().unwrap_or(0.0)
+}
+
+fn render(panels: Vec<Panel>, start: f32, end: f32, boundaries: Vec<f32>, options: &PlotOptions) -> String {
+    let plot_width = options.width - 2.0 * MARGIN;
+    let plot_height = options.panel_height - 2.0 * MARGIN;
+    let span = if end > start { end - start } else { 1.0 };
+    let x = |t: f32| MARGIN + (t - start) / span * plot_width;
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\">\n",
+        options.width, options.panel_height * panels.len() as f32);
+    for (i, panel) in panels.iter().enumerate() {
+        let top = options.panel_height * i as f32 + MARGIN;
+        let min = panel.points.iter().map(|p| p.1).fold(::std::f32::INFINITY, f32::min);
+        let max = panel.points.iter().map(|p| p.1).fold(::std::f32::NEG_INFINITY, f32::max);
+        let (min, max) = if max > min { (min, max) } else { (min - 1.0, min + 1.0) };
+        let y = |v: f32| top + (1.0 - (v - min) / (max - min)) * plot_height;
+        svg.push_str(&format!("  <g>\n    <text x=\"{:.2}\" y=\"{:.2}\" font-size=\"12\">{} [{} .. {}]</text>\n",
+            MARGIN, top - 8.0, escape_xml(&panel.label), min, max));
+        svg.push_str(&format!("    <rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" fill=\"none\" stroke=\"#ccc\"/>\n",
+            MARGIN, top, plot_width, plot_height));
+        for &t in &boundaries {
+            svg.push_str(&format!("    <line class=\"loop\" x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"#999\" stroke-dasharray=\"4,4\"/>\n",
+                x(t), top, x(t), top + plot_height));
+        }
+        let points: Vec<String> = panel.points.iter().map(|&(t, v)| format!("{:.2},{:.2}", x(t), y(v))).collect();
+        svg.push_str(&format!("    <polyline fill=\"none\" stroke=\"#1f77b4\" points=\"{}\"/>\n", points.join(" ")));
+        let tangent_length = span * 0.04;
+        for &(t, v, in_slope, out_slope) in &panel.keys {
+            svg.push_str(&format!("    <line class=\"tangent\" x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"#d62728\"/>\n",
+                x(t - tangent_length), y(v - in_slope * tangent_length), x(t + tangent_length), y(v + out_slope * tangent_length)));
+            svg.push_str(&format!("    <circle class=\"key\" cx=\"{:.2}\" cy=\"{:.2}\" r=\"3\" fill=\"#d62728\"/>\n", x(t), y(v)));
+        }
+        svg.push_str("  </g>\n");
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Plots every component of a curve between `start` and `end`, with a marker at each key and
+/// the curve's incoming and outgoing slope at the key.
+pub fn curve_to_svg(curve: &Curve<Animatable>, start: f32, end: f32, options: &PlotOptions) -> String {
+    let times = sample_times(start, end, options.samples);
+    let values: Vec<Animatable> = times.iter().map(|&t| curve.value(t)).collect();
+    let components = values.iter().map(|v| v.value.len()).max().unwrap_or(0);
+    let h = (end - start).abs().max(0.001) * 0.001;
+    let key_times: Vec<f32> = curve.key_times().into_iter().filter(|&t| t >= start && t <= end).collect();
+    let key_values: Vec<(Animatable, Animatable, Animatable)> = key_times.iter()
+        .map(|&t| (curve.value(t - h), curve.value(t), curve.value(t + h))).collect();
+    let panels = (0..components).map(|c| Panel {
+        label: format!("[{}]", c),
+        points: times.iter().zip(values.iter()).map(|(&t, v)| (t, component(v, c))).collect(),
+        keys: key_times.iter().zip(key_values.iter()).map(|(&t, &(ref before, ref at, ref after))| {
+            let v = component(at, c);
+            (t, v, (v - component(before, c)) / h, (component(after, c) - v) / h)
+        }).collect()
+    }).collect();
+    render(panels, start, end, vec![], options)
+}
+
+fn property_value(values: &Vec<(NamedPropRef, Animatable)>, property: &NamedPropRef) -> Option<Animatable> {
+    values.iter().find(|&&(ref p, _)| p == property).map(|&(_, ref v)| v.clone())
+}
+
+/// Plots every component of every property a track drives from zero to `end`, with dashed
+/// lines where the track loops. Tracks that stop when they end get no loop lines. Keys are
+/// marked in every loop, with the track's velocity just before and just after the key.
+pub fn track_to_svg(track: &Track, end: Duration, options: &PlotOptions) -> String {
+    let end = end.num_milliseconds() as f32 / 1000.0;
+    let times = sample_times(0.0, end, options.samples);
+    let at = |t: f32| Duration::milliseconds((t * 1000.0).round() as i64);
+    let samples: Vec<Vec<(NamedPropRef, Animatable)>> = times.iter().map(|&t| track.value_at(at(t))).collect();
+    let duration = track.duration().num_milliseconds() as f32 / 1000.0;
+    let loops = track.value_at(track.duration() + Duration::milliseconds(1)).len() > 0 && duration > 0.0;
+    let mut key_times: Vec<f32> = vec![];
+    for t in track.key_times() {
+        let mut t = t;
+        while t >= 0.0 && t <= end {
+            key_times.push(t);
+            if !loops { break; }
+            t += duration;
+        }
+    }
+    key_times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    key_times.dedup();
+    let h = Duration::milliseconds(1);
+    let key_samples: Vec<(f32, Vec<(NamedPropRef, Animatable)>, Vec<(NamedPropRef, Animatable)>, Vec<(NamedPropRef, Animatable)>)> = key_times.iter()
+        .map(|&t| {
+            let time = at(t);
+            let before = if time > h { time - h } else { Duration::zero() };
+            (t, track.value_at(time), track.velocity_at(before, &NoProperties), track.velocity_at(time + h, &NoProperties))
+        }).collect();
+    let mut panels = vec![];
+    for property in track.properties() {
+        let points: Vec<(f32, Animatable)> = times.iter().zip(samples.iter())
+            .filter_map(|(&t, values)| property_value(values, &property).map(|v| (t, v)))
+            .collect();
+        let keys: Vec<(f32, Animatable, Animatable, Animatable)> = key_samples.iter()
+            .filter_map(|&(t, ref values, ref before, ref after)| match (property_value(values, &property),
+                    property_value(before, &property), property_value(after, &property)) {
+                (Some(value), Some(before), Some(after)) => Some((t, value, before, after)),
+                _ => None
+            })
+            .collect();
+        let components = points.iter().map(|&(_, ref v)| v.value.len()).max().unwrap_or(0);
+        let name = Pon::Reference(property.clone()).to_string();
+        for c in 0..components {
+            panels.push(Panel {
+                label: if components == 1 { name.clone() } else { format!("{}[{}]", name, c) },
+                points: points.iter().map(|&(t, ref v)| (t, component(v, c))).collect(),
+                keys: keys.iter().map(|&(t, ref v, ref before, ref after)| (t, component(v, c), component(before, c), component(after, c))).collect()
+            });
+        }
+    }
+    let boundaries = if loops {
+        (1..).map(|i| i as f32 * duration).take_while(|&t| t < end).collect()
+    } else {
+        vec![]
+    };
+    render(panels, 0.0, end, boundaries, options)
+}
+
+
+#[test]
+fn test_curve_to_svg() {
+    let curve = LinearKeyFrameCurve::new(
+        vec![Key(0.0, Animatable::new(vec![0.0, 1.0])), Key(1.0, Animatable::new(vec![1.0, 1.0])), Key(2.0, Animatable::new(vec![0.0, 1.0]))]
+    ).unwrap();
+    let options = PlotOptions { width: 200.0, panel_height: 100.0, samples: 3 };
+    assert_eq!(curve_to_svg(&curve, 0.0, 2.0, &options), r##"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="200">
+  <g>
+    <text x="30.00" y="22.00" font-size="12">[0] [0 .. 1]</text>
+    <rect x="30.00" y="30.00" width="140.00" height="40.00" fill="none" stroke="#ccc"/>
+    <polyline fill="none" stroke="#1f77b4" points="30.00,70.00 100.00,30.00 170.00,70.00"/>
+    <line class="tangent" x1="24.40" y1="70.00" x2="35.60" y2="66.80" stroke="#d62728"/>
+    <circle class="key" cx="30.00" cy="70.00" r="3" fill="#d62728"/>
+    <line class="tangent" x1="94.40" y1="33.20" x2="105.60" y2="33.20" stroke="#d62728"/>
+    <circle class="key" cx="100.00" cy="30.00" r="3" fill="#d62728"/>
+    <line class="tangent" x1="164.40" y1="66.80" x2="175.60" y2="70.00" stroke="#d62728"/>
+    <circle class="key" cx="170.00" cy="70.00" r="3" fill="#d62728"/>
+  </g>
+  <g>
+    <text x="30.00" y="122.00" font-size="12">[1] [0 .. 2]</text>
+    <rect x="30.00" y="130.00" width="140.00" height="40.00" fill="none" stroke="#ccc"/>
+    <polyline fill="none" stroke="#1f77b4" points="30.00,150.00 100.00,150.00 170.00,150.00"/>
+    <line class="tangent" x1="24.40" y1="150.00" x2="35.60" y2="150.00" stroke="#d62728"/>
+    <circle class="key" cx="30.00" cy="150.00" r="3" fill="#d62728"/>
+    <line class="tangent" x1="94.40" y1="150.00" x2="105.60" y2="150.00" stroke="#d62728"/>
+    <circle class="key" cx="100.00" cy="150.00" r="3" fill="#d62728"/>
+    <line class="tangent" x1="164.40" y1="150.00" x2="175.60" y2="150.00" stroke="#d62728"/>
+    <circle class="key" cx="170.00" cy="150.00" r="3" fill="#d62728"/>
+  </g>
+</svg>
+"##);
+}
+
+#[test]
+fn test_track_to_svg() {
+    let track: ::curve_track::CurveTrack = Pon::from_string(
+        "key_framed { property: this.x, keys: [[0.0, 0.0], [1.0, 1.0]], loop: 'forever' }")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    let options = PlotOptions { width: 200.0, panel_height: 100.0, samples: 3 };
+    let label = escape_xml(&Pon::Reference(NamedPropRef::new(EntityPath::This, "x")).to_string());
+    assert_eq!(track_to_svg(&track, Duration::seconds(2), &options), format!(r##"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="100">
+  <g>
+    <text x="30.00" y="22.00" font-size="12">{} [0 .. 1]</text>
+    <rect x="30.00" y="30.00" width="140.00" height="40.00" fill="none" stroke="#ccc"/>
+    <line class="loop" x1="100.00" y1="30.00" x2="100.00" y2="70.00" stroke="#999" stroke-dasharray="4,4"/>
+    <polyline fill="none" stroke="#1f77b4" points="30.00,70.00 100.00,30.00 170.00,70.00"/>
+    <line class="tangent" x1="24.40" y1="73.20" x2="35.60" y2="66.80" stroke="#d62728"/>
+    <circle class="key" cx="30.00" cy="70.00" r="3" fill="#d62728"/>
+    <line class="tangent" x1="94.40" y1="33.20" x2="105.60" y2="26.80" stroke="#d62728"/>
+    <circle class="key" cx="100.00" cy="30.00" r="3" fill="#d62728"/>
+    <line class="tangent" x1="164.40" y1="73.20" x2="175.60" y2="66.80" stroke="#d62728"/>
+    <circle class="key" cx="170.00" cy="70.00" r="3" fill="#d62728"/>
+  </g>
+</svg>
+"##, label));
+}
+
+#[test]
+fn test_escape_xml() {
+    assert_eq!(escape_xml("a<b & 'c' > \"d\""), "a&lt;b &amp; &apos;c&apos; &gt; &quot;d&quot;".to_string());
+}
+
+#[test]
+fn test_track_to_svg_loop_boundaries() {
+    let track: ::curve_track::CurveTrack = Pon::from_string(
+        "key_framed { property: this.x, keys: [[0.0, 0.0], [1.0, 1.0]], loop: 'forever' }")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    let svg = track_to_svg(&track, Duration::seconds(3), &PlotOptions::new());
+    assert_eq!(svg.matches("class=\"loop\"").count(), 2);
+}
+
+#[test]
+fn test_track_to_svg_no_loop_boundaries_once() {
+    let track: ::curve_track::CurveTrack = Pon::from_string(
+        "key_framed { property: this.x, keys: [[0.0, 0.0], [1.0, 1.0]], loop: 'once' }")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    let svg = track_to_svg(&track, Duration::seconds(3), &PlotOptions::new());
+    assert_eq!(svg.matches("class=\"loop\"").count(), 0);
+}
@@ -0,0 +1,164 @@
+
+use std::cmp::Ordering;
+use time::*;
+use pyramid::pon::*;
+use pyramid::document::*;
+use animatable::*;
+use curve::*;
+use curve_track::*;
+use track::*;
+use validate::*;
+
+#[derive(Debug)]
+pub enum TimeRemap {
+    /// The child plays at `rate` times normal speed, starting `offset` seconds in. A negative
+    /// rate plays it backwards.
+    Linear { rate: f32, offset: f32 },
+    /// Maps the time in seconds to the child's time in seconds through the first component
+    /// of a curve, for slow motion ramps and the like
+    Curve(Box<AnimationCurve>)
+}
+
+/// Plays a track with its time remapped.
+#[derive(Debug)]
+pub struct TimeRemapTrack {
+    pub track: Box<Track>,
+    pub remap: TimeRemap
+}
+
+fn seconds(time: Duration) -> f32 {
+    time.num_milliseconds() as f32 / 1000.0
+}
+
+fn from_seconds(time: f32) -> Duration {
+    Duration::milliseconds((time * 1000.0).round() as i64)
+}
+
+impl TimeRemapTrack {
+    /// The child's time at `time`
+    pub fn remap_time(&self, time: Duration) -> Duration {
+        let time = seconds(time);
+        from_seconds(match self.remap {
+            TimeRemap::Linear { rate, offset } => rate * time + offset,
+            TimeRemap::Curve(ref curve) => curve.value(time).value.get(0).cloned().unwrap_or(0.0)
+        })
+    }
+}
+
+impl Track for TimeRemapTrack {
+    fn value_at(&self, time: Duration) -> Vec<(NamedPropRef, Animatable)> {
+        self.track.value_at(self.remap_time(time))
+    }
+    fn value_at_with(&self, time: Duration, properties: &PropertySource) -> Vec<(NamedPropRef, Animatable)> {
+        self.track.value_at_with(self.remap_time(time), properties)
+    }
+    fn advance(&mut self, time: Duration, delta: Duration, properties: &PropertySource) {
+        let (from, to) = (self.remap_time(time - delta), self.remap_time(time));
+        self.track.advance(to, to - from, properties);
+    }
+    fn duration(&self) -> Duration {
+        match self.remap {
+            TimeRemap::Linear { rate, .. } if rate != 0.0 => from_seconds(seconds(self.track.duration()) / rate.abs()),
+            TimeRemap::Linear { .. } => self.track.duration(),
+            TimeRemap::Curve(ref curve) => match curve.key_times().last() {
+                Some(&end) => from_seconds(end),
+                None => self.track.duration()
+            }
+        }
+    }
+    fn properties(&self) -> Vec<NamedPropRef> {
+        self.track.properties()
+    }
+    /// The times the child's keys are played at. A remap curve is treated as linear between
+    /// its own keys, which are included too since the playback speed changes at them.
+    fn key_times(&self) -> Vec<f32> {
+        let child_keys = self.track.key_times();
+        let mut times: Vec<f32> = match self.remap {
+            TimeRemap::Linear { rate, offset } if rate != 0.0 => child_keys.iter().map(|&k| (k - offset) / rate).collect(),
+            TimeRemap::Linear { .. } => vec![],
+            TimeRemap::Curve(ref curve) => {
+                let remap_keys: Vec<(f32, f32)> = curve.key_times().into_iter()
+                    .map(|t| (t, curve.value(t).value.get(0).cloned().unwrap_or(0.0))).collect();
+                let mut times: Vec<f32> = remap_keys.iter().map(|&(t, _)| t).collect();
+                for pair in remap_keys.windows(2) {
+                    let ((t0, v0), (t1, v1)) = (pair[0], pair[1]);
+                    if v0 != v1 {
+                        times.extend(child_keys.iter().filter(|&&k| (k - v0) * (k - v1) <= 0.0)
+                            .map(|&k| t0 + (k - v0) / (v1 - v0) * (t1 - t0)));
+                    }
+                }
+                times
+            }
+        };
+        times.retain(|&t| t >= 0.0);
+        times.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        times.dedup();
+        times
+    }
+    fn refresh_resources(&mut self, document: &Document) -> Vec<Diagnostic> {
+        self.track.refresh_resources(document)
+    }
+    fn is_stateful(&self) -> bool {
+        self.track.is_stateful()
+    }
+}
+
+impl ToPon for TimeRemapTrack {
+    fn to_pon(&self) -> Pon {
+        let mut data = match self.remap {
+            TimeRemap::Linear { rate, offset } => object_pon(vec![("rate", rate.to_pon()), ("offset", offset.to_pon())]),
+            TimeRemap::Curve(ref curve) => match curve.to_pon() {
+                Pon::TypedPon(box TypedPon { data, .. }) => data,
+                _ => object_pon(vec![])
+            }
+        };
+        if let Pon::Object(ref mut fields) = data {
+            fields.insert("track".to_string(), self.track.to_pon());
+        }
+        typed_pon("time_remap", data)
+    }
+}
+
+impl Translatable<TimeRemapTrack> for Pon {
+    fn inner_translate(&self, context: &mut TranslateContext) -> Result<TimeRemapTrack, PonTranslateErr> {
+        self.as_typed(|&TypedPon { ref data, .. }| -> Result<TimeRemapTrack, PonTranslateErr> {
+            let remap = match data.field("keys") {
+                Ok(_) => TimeRemap::Curve(try!(translate_key_framed_curve(data, context))),
+                Err(_) => TimeRemap::Linear {
+                    rate: try!(data.field_as_or("rate", 1.0, context)),
+                    offset: try!(data.field_as_or("offset", 0.0, context))
+                }
+            };
+            Ok(TimeRemapTrack {
+                track: try!(data.field_as::<Box<Track>>("track", context)),
+                remap: remap
+            })
+        })
+    }
+}
+
+
+#[test]
+fn test_time_remap_reverse() {
+    let track: TimeRemapTrack = Pon::from_string(
+        "time_remap { track: key_framed { property: this.x, keys: [[0.0, 0.0], [2.0, 1.0]], duration: 2.0 }, rate: -2.0, offset: 2.0 }")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    let x = NamedPropRef::new(EntityPath::This, "x");
+    assert_eq!(track.value_at(Duration::zero()), vec![(x.clone(), Animatable::new_float(1.0))]);
+    assert_eq!(track.value_at(Duration::milliseconds(500)), vec![(x.clone(), Animatable::new_float(0.5))]);
+    assert_eq!(track.duration(), Duration::seconds(1));
+    assert_eq!(track.key_times(), vec![0.0, 1.0]);
+}
+
+#[test]
+fn test_time_remap_curve() {
+    let track: TimeRemapTrack = Pon::from_string(
+        "time_remap { track: key_framed { property: this.x, keys: [[0.0, 0.0], [1.0, 1.0]] }, keys: [[0.0, 0.0], [2.0, 0.5], [3.0, 1.0]] }")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    let x = NamedPropRef::new(EntityPath::This, "x");
+    assert_eq!(track.value_at(Duration::seconds(1)), vec![(x.clone(), Animatable::new_float(0.25))]);
+    assert_eq!(track.duration(), Duration::seconds(3));
+    assert_eq!(track.key_times(), vec![0.0, 2.0, 3.0]);
+    let roundtrip: TimeRemapTrack = track.to_pon().translate(&mut TranslateContext::empty()).unwrap();
+    assert_eq!(roundtrip.to_pon(), track.to_pon());
+}
@@ -1,19 +1,79 @@
 
 use time::*;
 use pyramid::pon::*;
+use pyramid::document::*;
 use curve_track::*;
 use track_set::*;
 use weighted_tracks::*;
+use layers::*;
 use animatable::*;
+use curve::*;
+use oscillator::*;
+use expression::*;
+use spring::*;
+use driven::*;
+use time_remap::*;
+use clip_range::*;
+use path::*;
+use validate::*;
 use std::fmt::Debug;
 use std::rc::Rc;
 
-pub trait Track : Debug {
+/// Values of document properties, for tracks whose values depend on other properties.
+pub trait PropertySource {
+    fn property_value(&self, property: &NamedPropRef) -> Option<Animatable>;
+}
+
+/// A source without any properties, used when tracks are evaluated outside a document.
+pub struct NoProperties;
+
+impl PropertySource for NoProperties {
+    fn property_value(&self, _property: &NamedPropRef) -> Option<Animatable> { None }
+}
+
+pub trait Track : Debug + ToPon {
     fn value_at(&self, time: Duration) -> Vec<(NamedPropRef, Animatable)>;
+    /// Same as `value_at`, but tracks that read other properties read them from `properties`.
+    /// Tracks containing other tracks need to pass `properties` on to them.
+    fn value_at_with(&self, time: Duration, _properties: &PropertySource) -> Vec<(NamedPropRef, Animatable)> {
+        self.value_at(time)
+    }
+    /// Called once per update before the values are read, with the time since the track
+    /// started and the time since the previous update, for tracks that keep state between
+    /// updates. Tracks containing other tracks need to pass it on to them.
+    fn advance(&mut self, _time: Duration, _delta: Duration, _properties: &PropertySource) {}
+    /// The time it takes to play the track through once
+    fn duration(&self) -> Duration;
+    /// The properties the track drives
+    fn properties(&self) -> Vec<NamedPropRef>;
+    /// How fast each property changes at `time`, in value per second. Estimated from the
+    /// values 10 ms around `time`, read with `properties`, unless the track knows better.
+    fn velocity_at(&self, time: Duration, properties: &PropertySource) -> Vec<(NamedPropRef, Animatable)> {
+        let h = Duration::milliseconds(10);
+        let before = self.value_at_with(time - h, properties);
+        self.value_at_with(time + h, properties).into_iter().filter_map(|(property, after)| {
+            before.iter().find(|&&(ref p, _)| p == &property)
+                .map(|&(_, ref before)| (property.clone(), after.add_weighted(-1.0, before).weighted(1.0 / 0.02)))
+        }).collect()
+    }
+    /// The times, in seconds of track time, of the keys of the curves the track plays, for
+    /// tracks that have keys. Baking a track at these times keeps its authored keys.
+    fn key_times(&self) -> Vec<f32> { vec![] }
+    /// Called before each update so tracks backed by document resources can pick up
+    /// resources that have been replaced since the track was translated. Returns the
+    /// problems with replaced resources that could not be picked up.
+    fn refresh_resources(&mut self, _document: &Document) -> Vec<Diagnostic> { vec![] }
+    /// Whether the track, or any track inside it, keeps state between updates in `advance`.
+    /// Such tracks cannot be shared through resources.
+    fn is_stateful(&self) -> bool { false }
 }
 
+/// Plays a track set shared through a document resource. The set is shared by every track
+/// using the resource, so it cannot hold tracks that are advanced, which is checked when the
+/// track is translated and when the resource is replaced.
 #[derive(Debug)]
 struct TrackSetFromResource {
+    resource_id: String,
     resource: Rc<TrackSet>
 }
 
@@ -21,6 +81,101 @@ impl Track for TrackSetFromResource {
     fn value_at(&self, time: Duration) -> Vec<(NamedPropRef, Animatable)> {
         self.resource.value_at(time)
     }
+    fn value_at_with(&self, time: Duration, properties: &PropertySource) -> Vec<(NamedPropRef, Animatable)> {
+        self.resource.value_at_with(time, properties)
+    }
+    fn velocity_at(&self, time: Duration, properties: &PropertySource) -> Vec<(NamedPropRef, Animatable)> {
+        self.resource.velocity_at(time, properties)
+    }
+    fn duration(&self) -> Duration {
+        self.resource.duration()
+    }
+    fn properties(&self) -> Vec<NamedPropRef> {
+        self.resource.properties()
+    }
+    fn key_times(&self) -> Vec<f32> {
+        self.resource.key_times()
+    }
+    fn refresh_resources(&mut self, document: &Document) -> Vec<Diagnostic> {
+        let replacement = match document.resources.get(&self.resource_id).and_then(|r| r.downcast_ref::<Rc<TrackSet>>()) {
+            Some(resource) if &**resource as *const TrackSet != &*self.resource as *const TrackSet => resource.clone(),
+            _ => return vec![]
+        };
+        if replacement.is_stateful() {
+            return vec![Diagnostic {
+                location: self.resource_id.clone(),
+                message: "not reloaded: the new track set keeps state between updates, so it cannot be shared through resources".to_string(),
+                severity: Severity::Warning
+            }];
+        }
+        self.resource = replacement;
+        vec![]
+    }
+    fn is_stateful(&self) -> bool {
+        self.resource.is_stateful()
+    }
+}
+
+impl ToPon for TrackSetFromResource {
+    fn to_pon(&self) -> Pon {
+        typed_pon("track_set_from_resource", Pon::String(self.resource_id.clone()))
+    }
+}
+
+fn translate_track_set_from_resource(resource_id: String, context: &TranslateContext) -> Result<TrackSetFromResource, PonTranslateErr> {
+    let document = match context.document {
+        Some(document) => document,
+        None => return Err(PonTranslateErr::InvalidValue { value: format!("track set resource {} can only be used in a document", resource_id) })
+    };
+    let track_set = match document.resources.get(&resource_id) {
+        Some(resource) => match resource.downcast_ref::<Rc<TrackSet>>() {
+            Some(track_set) => track_set.clone(),
+            None => return Err(PonTranslateErr::InvalidValue { value: format!("resource {} is not a track set", resource_id) })
+        },
+        None => return Err(PonTranslateErr::InvalidValue { value: format!("no track set resource named {}", resource_id) })
+    };
+    if track_set.is_stateful() {
+        return Err(PonTranslateErr::InvalidValue { value: format!("track set resource {} keeps state between updates, so it cannot be shared through resources", resource_id) });
+    }
+    Ok(TrackSetFromResource { resource_id: resource_id, resource: track_set })
+}
+
+/// The properties driven by any of the tracks, in the order they first appear.
+pub fn collect_properties<'a, I: Iterator<Item=&'a Track>>(tracks: I) -> Vec<NamedPropRef> {
+    let mut res = vec![];
+    for track in tracks {
+        for property in track.properties() {
+            if !res.contains(&property) {
+                res.push(property);
+            }
+        }
+    }
+    res
+}
+
+/// Samples a track at least `sample_rate` times per second from zero up to and including
+/// `duration`, returning the keys for each property in the order the properties first appear.
+pub fn sample_track(track: &Track, duration: Duration, sample_rate: f32) -> Vec<(NamedPropRef, Vec<Key<Animatable>>)> {
+    let times = SampleTimes::new(0.0, duration.num_milliseconds() as f32 / 1000.0, sample_rate);
+    let mut res: Vec<(NamedPropRef, Vec<Key<Animatable>>)> = vec![];
+    for i in 0..times.count() {
+        let time = times.time(i);
+        for (property, value) in track.value_at(Duration::milliseconds((time * 1000.0).round() as i64)) {
+            match res.iter().position(|&(ref p, _)| p == &property) {
+                Some(index) => res[index].1.push(Key(time, value)),
+                None => res.push((property, vec![Key(time, value)]))
+            }
+        }
+    }
+    res
+}
+
+pub fn typed_pon(type_name: &str, data: Pon) -> Pon {
+    Pon::TypedPon(Box::new(TypedPon { type_name: type_name.to_string(), data: data }))
+}
+
+pub fn object_pon(fields: Vec<(&str, Pon)>) -> Pon {
+    Pon::Object(fields.into_iter().map(|(name, value)| (name.to_string(), value)).collect())
 }
 
 impl Translatable<Box<Track>> for Pon {
@@ -29,15 +184,89 @@ impl Translatable<Box<Track>> for Pon {
             match type_name.as_str() {
                 "key_framed" => Ok(Box::new(try!(self.translate::<CurveTrack>(context)))),
                 "fixed_value" => Ok(Box::new(try!(self.translate::<CurveTrack>(context)))),
+                s if OSCILLATOR_TYPES.contains(&s) => Ok(Box::new(try!(self.translate::<CurveTrack>(context)))),
+                "expression" => Ok(Box::new(try!(self.translate::<ExpressionTrack>(context)))),
+                "path" => Ok(Box::new(try!(self.translate::<PathTrack>(context)))),
+                "clip_range" => Ok(Box::new(try!(self.translate::<ClipRangeTrack>(context)))),
+                "time_remap" => Ok(Box::new(try!(self.translate::<TimeRemapTrack>(context)))),
+                "driven" => Ok(Box::new(try!(self.translate::<DrivenTrack>(context)))),
+                "spring" | "smooth_follow" => Ok(Box::new(try!(self.translate::<SpringTrack>(context)))),
                 "track_set" => Ok(Box::new(try!(self.translate::<TrackSet>(context)))),
                 "weighted_tracks" => Ok(Box::new(try!(self.translate::<WeightedTracks>(context)))),
+                "layers" => Ok(Box::new(try!(self.translate::<LayerStack>(context)))),
                 "track_set_from_resource" => {
                     let resource_id = try!(data.translate::<String>(context));
-                    let track_set = context.document.unwrap().resources.get(&resource_id).unwrap().downcast_ref::<Rc<TrackSet>>().unwrap().clone();
-                    return Ok(Box::new(TrackSetFromResource { resource: track_set }));
+                    Ok(Box::new(try!(translate_track_set_from_resource(resource_id, context))))
                 },
                 s @ _ => Err(PonTranslateErr::UnrecognizedType(s.to_string()))
             }
         })
     }
 }
+
+
+#[cfg(test)]
+fn test_resource_document(keys: &str) -> Document {
+    let mut document = Document::new();
+    let track_set: TrackSet = Pon::from_string(&format!("track_set [ key_framed {{ property: this.x, keys: {} }} ]", keys))
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    ::loader::register_track_set(&mut document, "walk", track_set);
+    document
+}
+
+#[test]
+fn test_track_set_from_resource_reload() {
+    let document = test_resource_document("[[0.0, 0.0], [1.0, 1.0]]");
+    let mut track = translate_track_set_from_resource("walk".to_string(), &TranslateContext { document: Some(&document) }).unwrap();
+    assert_eq!(track.value_at(Duration::milliseconds(500)), vec![(NamedPropRef::new(EntityPath::This, "x"), Animatable::new_float(0.5))]);
+    let replaced = test_resource_document("[[0.0, 0.0], [1.0, 2.0]]");
+    track.refresh_resources(&replaced);
+    // The replacement is played from the same time, not restarted
+    assert_eq!(track.value_at(Duration::milliseconds(500)), vec![(NamedPropRef::new(EntityPath::This, "x"), Animatable::new_float(1.0))]);
+    assert_eq!(track.value_at(Duration::milliseconds(750)), vec![(NamedPropRef::new(EntityPath::This, "x"), Animatable::new_float(1.5))]);
+}
+
+#[test]
+fn test_track_set_from_resource_same_resource() {
+    let document = test_resource_document("[[0.0, 0.0], [1.0, 1.0]]");
+    let mut track = translate_track_set_from_resource("walk".to_string(), &TranslateContext { document: Some(&document) }).unwrap();
+    let resource = &*track.resource as *const TrackSet;
+    track.refresh_resources(&document);
+    assert_eq!(&*track.resource as *const TrackSet, resource);
+    track.refresh_resources(&Document::new());
+    assert_eq!(&*track.resource as *const TrackSet, resource);
+}
+
+#[test]
+fn test_track_set_from_resource_missing() {
+    let source = Pon::from_string("track_set_from_resource 'run'").unwrap();
+    assert!(source.translate::<Box<Track>>(&mut TranslateContext::empty()).is_err());
+    let document = test_resource_document("[[0.0, 0.0], [1.0, 1.0]]");
+    assert!(source.translate::<Box<Track>>(&mut TranslateContext { document: Some(&document) }).is_err());
+}
+
+#[test]
+fn test_track_set_from_resource_stateful() {
+    let document = test_resource_document("[[0.0, 0.0], [1.0, 1.0]]");
+    let mut track = translate_track_set_from_resource("walk".to_string(), &TranslateContext { document: Some(&document) }).unwrap();
+    let mut replaced = Document::new();
+    let springs: TrackSet = Pon::from_string("track_set [ spring { property: this.x, target: 1.0 } ]")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    assert!(springs.is_stateful());
+    ::loader::register_track_set(&mut replaced, "walk", springs);
+    assert_eq!(track.refresh_resources(&replaced).len(), 1);
+    assert_eq!(track.value_at(Duration::milliseconds(500)), vec![(NamedPropRef::new(EntityPath::This, "x"), Animatable::new_float(0.5))]);
+    assert!(translate_track_set_from_resource("walk".to_string(), &TranslateContext { document: Some(&replaced) }).is_err());
+}
+
+#[test]
+fn test_sample_track_uneven_duration() {
+    let track: CurveTrack = Pon::from_string("key_framed { property: this.x, keys: [[0.0, 0.0], [1.0, 1.0]], duration: 1.1 }")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    let sampled = sample_track(&track, Duration::milliseconds(1100), 2.0);
+    let times: Vec<f32> = sampled[0].1.iter().map(|key| key.0).collect();
+    // Evenly spaced, ending on the duration without a duplicate last key
+    assert_eq!(times.len(), 4);
+    assert_eq!(times[0], 0.0);
+    assert!((times[3] - 1.1).abs() < 0.0001 && times[2] < times[3]);
+}
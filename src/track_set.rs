@@ -3,7 +3,9 @@ use std::cmp::Ordering;
 
 use time::*;
 use track::*;
+use validate::*;
 use pyramid::pon::*;
+use pyramid::document::*;
 use animatable::*;
 
 #[derive(Debug)]
@@ -13,14 +15,46 @@ pub struct TrackSet {
 
 impl Track for TrackSet {
     fn value_at(&self, time: Duration) -> Vec<(NamedPropRef, Animatable)> {
+        self.value_at_with(time, &NoProperties)
+    }
+    fn value_at_with(&self, time: Duration, properties: &PropertySource) -> Vec<(NamedPropRef, Animatable)> {
         let mut res = vec![];
         for track in &self.tracks {
-            for update in track.value_at(time).into_iter() {
+            for update in track.value_at_with(time, properties).into_iter() {
                 res.push(update);
             }
         }
         res
     }
+    fn velocity_at(&self, time: Duration, properties: &PropertySource) -> Vec<(NamedPropRef, Animatable)> {
+        self.tracks.iter().flat_map(|track| track.velocity_at(time, properties).into_iter()).collect()
+    }
+    fn duration(&self) -> Duration {
+        self.tracks.iter().map(|track| track.duration()).max().unwrap_or(Duration::zero())
+    }
+    fn properties(&self) -> Vec<NamedPropRef> {
+        collect_properties(self.tracks.iter().map(|track| &**track))
+    }
+    fn key_times(&self) -> Vec<f32> {
+        self.tracks.iter().flat_map(|track| track.key_times().into_iter()).collect()
+    }
+    fn advance(&mut self, time: Duration, delta: Duration, properties: &PropertySource) {
+        for track in self.tracks.iter_mut() {
+            track.advance(time, delta, properties);
+        }
+    }
+    fn refresh_resources(&mut self, document: &Document) -> Vec<Diagnostic> {
+        self.tracks.iter_mut().flat_map(|track| track.refresh_resources(document).into_iter()).collect()
+    }
+    fn is_stateful(&self) -> bool {
+        self.tracks.iter().any(|track| track.is_stateful())
+    }
+}
+
+impl ToPon for TrackSet {
+    fn to_pon(&self) -> Pon {
+        typed_pon("track_set", Pon::Array(self.tracks.iter().map(|track| track.to_pon()).collect()))
+    }
 }
 
 impl Translatable<TrackSet> for Pon {
@@ -49,3 +83,13 @@ fn test_track_set_from_pon() {
         (NamedPropRef::new(EntityPath::This, "y"), 0.2)
     ].sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal)));
 }
+
+#[test]
+fn test_track_set_to_pon_roundtrip() {
+    let anim_set: TrackSet = Pon::from_string(
+        "track_set [ fixed_value { property: this.x, value: 0.5 }, key_framed { property: this.y, keys: [[0.0, 0.0], [1.0, 1.0]] } ]")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    let pon = anim_set.to_pon();
+    let roundtrip: TrackSet = pon.translate(&mut TranslateContext::empty()).unwrap();
+    assert_eq!(roundtrip.to_pon(), pon);
+}
@@ -0,0 +1,365 @@
+
+use std::fmt;
+use std::rc::Rc;
+use pyramid::pon::*;
+use pyramid::document::*;
+use animatable::*;
+use curve::*;
+use track::*;
+use track_set::*;
+use oscillator::*;
+use expression::*;
+use spring::*;
+use path::*;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Severity {
+    /// The animation cannot be translated
+    Error,
+    /// The animation translates, but likely does not do what was intended
+    Warning
+}
+
+/// A problem found in an animation, with the path to the PON value it was found in,
+/// e.g. `animation.track_set[1].keys[3]`.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Diagnostic {
+    pub location: String,
+    pub message: String,
+    pub severity: Severity
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "{}: {}", self.location, self.message),
+            Severity::Warning => write!(f, "{}: warning: {}", self.location, self.message)
+        }
+    }
+}
+
+struct Validator<'a> {
+    /// The document and the entity property references are resolved from, if available
+    document: Option<(&'a Document, &'a EntityId)>,
+    diagnostics: Vec<Diagnostic>
+}
+
+fn elements(pon: &Pon) -> Vec<&Pon> {
+    match pon {
+        &Pon::Array(ref arr) => arr.iter().collect(),
+        &Pon::FloatArray(ref arr) if arr.len() == 0 => vec![],
+        _ => vec![pon]
+    }
+}
+
+impl<'a> Validator<'a> {
+    fn report(&mut self, location: &str, message: String) {
+        self.diagnostics.push(Diagnostic { location: location.to_string(), message: message, severity: Severity::Error });
+    }
+    fn warn(&mut self, location: &str, message: String) {
+        self.diagnostics.push(Diagnostic { location: location.to_string(), message: message, severity: Severity::Warning });
+    }
+    fn context(&self) -> TranslateContext<'a> {
+        TranslateContext { document: self.document.map(|(document, _)| document) }
+    }
+
+    fn property(&mut self, data: &Pon, field: &str, location: &str) {
+        let location = format!("{}.{}", location, field);
+        let property = match data.field(field) {
+            Ok(pon) => match pon.as_reference() {
+                Ok(property) => property.clone(),
+                Err(_) => return self.report(&location, format!("expected a property reference, found {}", pon.to_string()))
+            },
+            Err(_) => return self.report(&location, "missing property reference".to_string())
+        };
+        self.reference(&property, &location);
+    }
+
+    fn reference(&mut self, property: &NamedPropRef, location: &str) {
+        let unresolved = match self.document {
+            Some((document, entity_id)) => document.resolve_named_prop_ref(entity_id, property).is_err(),
+            None => false
+        };
+        if unresolved {
+            self.report(location, format!("cannot resolve {}", Pon::Reference(property.clone()).to_string()));
+        }
+    }
+
+    fn one_of(&mut self, data: &Pon, field: &str, allowed: &[&str], location: &str) -> Option<String> {
+        let pon = match data.field(field) {
+            Ok(pon) => pon,
+            Err(_) => return None
+        };
+        let location = format!("{}.{}", location, field);
+        let found = match pon.translate::<String>(&mut self.context()) {
+            Ok(value) => {
+                if allowed.contains(&value.as_str()) {
+                    return Some(value);
+                }
+                value
+            },
+            Err(_) => pon.to_string()
+        };
+        self.report(&location, format!("expected one of {}, found {}", allowed.join(", "), found));
+        None
+    }
+
+    fn key_framed(&mut self, data: &Pon, location: &str) {
+        self.property(data, "property", location);
+        self.curve_keys(data, location);
+    }
+
+    /// Checks the `loop` and `curve_time` of a curve track, returning whether the curve time is relative.
+    fn curve_timing(&mut self, data: &Pon, location: &str) -> bool {
+        self.one_of(data, "loop", &["forever", "once"], location);
+        self.one_of(data, "curve_time", &["absolute", "relative"], location) == Some("relative".to_string())
+    }
+
+    fn curve_keys(&mut self, data: &Pon, location: &str) {
+        let relative = self.curve_timing(data, location);
+        let interpolation = self.one_of(data, "interpolation", &["linear", "step", "discreet", "cubic_spline"], location);
+        let keys_location = format!("{}.keys", location);
+        let keys = match data.field("keys") {
+            Ok(keys) => elements(keys),
+            Err(_) => return self.report(&keys_location, "missing keys".to_string())
+        };
+        if keys.len() == 0 {
+            return self.report(&keys_location, "a key framed track needs at least one key".to_string());
+        }
+        let mut previous: Option<(f32, usize)> = None;
+        for (i, key) in keys.iter().enumerate() {
+            let location = format!("{}[{}]", keys_location, i);
+            let translated = if interpolation == Some("cubic_spline".to_string()) {
+                key.translate::<SplineKey>(&mut self.context()).map(|key| Key(key.time, key.value))
+            } else {
+                key.translate::<Key<Animatable>>(&mut self.context())
+            };
+            let Key(time, value) = match translated {
+                Ok(key) => key,
+                Err(err) => {
+                    self.report(&location, err.to_string());
+                    continue;
+                }
+            };
+            if relative && (time < 0.0 || time > 1.0) {
+                self.report(&location, format!("time {} is outside 0..1 of a relative curve", time));
+            }
+            match previous {
+                Some((previous_time, _)) if time < previous_time =>
+                    self.report(&location, format!("time {} comes before the previous key at {}", time, previous_time)),
+                // Allowed, and makes the curve jump, but is easily written by mistake
+                Some((previous_time, _)) if time == previous_time =>
+                    self.warn(&location, format!("duplicate key time {} makes the curve jump", time)),
+                _ => {}
+            }
+            match previous {
+                Some((_, components)) if components != value.value.len() =>
+                    self.report(&location, format!("value has {} components, the previous key has {}", value.value.len(), components)),
+                _ => {}
+            }
+            previous = Some((time, value.value.len()));
+        }
+    }
+
+    fn track(&mut self, pon: &Pon, location: &str) {
+        let (type_name, data) = match pon {
+            &Pon::TypedPon(box TypedPon { ref type_name, ref data }) => (type_name.as_str(), data),
+            _ => return self.report(location, format!("expected a track, found {}", pon.to_string()))
+        };
+        let location = format!("{}.{}", location, type_name);
+        match type_name {
+            "key_framed" => self.key_framed(data, &location),
+            "fixed_value" => {
+                self.property(data, "property", &location);
+                self.curve_timing(data, &location);
+                if let Err(err) = data.field_as::<Animatable>("value", &mut self.context()) {
+                    self.report(&format!("{}.value", location), err.to_string());
+                }
+            },
+            s if OSCILLATOR_TYPES.contains(&s) => {
+                self.property(data, "property", &location);
+                self.curve_timing(data, &location);
+                if let Err(err) = pon.translate::<OscillatorCurve>(&mut self.context()) {
+                    self.report(&location, err.to_string());
+                }
+            },
+            "expression" => {
+                self.property(data, "property", &location);
+                let value = match data.field("value") {
+                    Ok(value) => value,
+                    Err(_) => return self.report(&format!("{}.value", location), "missing expression".to_string())
+                };
+                let expressions = elements(value);
+                for (i, expression) in expressions.iter().enumerate() {
+                    let location = if expressions.len() == 1 { format!("{}.value", location) } else { format!("{}.value[{}]", location, i) };
+                    match expression.translate::<Expression>(&mut self.context()) {
+                        Ok(expression) => for property in expression.properties() {
+                            self.reference(&property, &location);
+                        },
+                        Err(err) => self.report(&location, err.to_string())
+                    }
+                }
+            },
+            "path" => {
+                self.property(data, "property", &location);
+                if data.field("rotation").is_ok() {
+                    self.property(data, "rotation", &location);
+                }
+                self.one_of(data, "interpolation", &["linear", "catmull_rom", "bezier"], &location);
+                self.one_of(data, "loop", &["forever", "once"], &location);
+                if let Err(err) = pon.translate::<PathTrack>(&mut self.context()) {
+                    self.report(&location, err.to_string());
+                }
+            },
+            "clip_range" => {
+                match data.field("track") {
+                    Ok(track) => self.track(track, &format!("{}.track", location)),
+                    Err(_) => self.report(&location, "missing track".to_string())
+                }
+                self.one_of(data, "loop", &["forever", "once"], &location);
+                let start = match data.field_as_or("start", 0.0f32, &mut self.context()) {
+                    Ok(start) => start,
+                    Err(err) => return self.report(&format!("{}.start", location), err.to_string())
+                };
+                // Without an end the range ends where its track does
+                let end = match data.field("end") {
+                    Ok(end) => match end.translate::<f32>(&mut self.context()) {
+                        Ok(end) => end,
+                        Err(err) => return self.report(&format!("{}.end", location), err.to_string())
+                    },
+                    Err(_) => match data.field_as::<Box<Track>>("track", &mut self.context()) {
+                        Ok(track) => track.duration().num_milliseconds() as f32 / 1000.0,
+                        Err(_) => return
+                    }
+                };
+                if end < start {
+                    self.report(&format!("{}.end", location), format!("clip range ends at {} before it starts at {}", end, start));
+                }
+            },
+            "time_remap" => {
+                match data.field("track") {
+                    Ok(track) => self.track(track, &format!("{}.track", location)),
+                    Err(_) => self.report(&location, "missing track".to_string())
+                }
+                if data.field("keys").is_ok() {
+                    self.curve_keys(data, &location);
+                }
+            },
+            "driven" => {
+                self.property(data, "input", &location);
+                self.key_framed(data, &location);
+            },
+            "spring" | "smooth_follow" => {
+                self.property(data, "property", &location);
+                match data.field("target") {
+                    Ok(&Pon::Reference(ref property)) => self.reference(property, &format!("{}.target", location)),
+                    Ok(_) => {},
+                    Err(_) => return self.report(&format!("{}.target", location), "missing target".to_string())
+                }
+                if let Ok(mass) = data.field_as::<f32>("mass", &mut self.context()) {
+                    if mass <= 0.0 {
+                        return self.report(&format!("{}.mass", location), format!("mass must be positive, found {}", mass));
+                    }
+                }
+                if let Err(err) = pon.translate::<SpringTrack>(&mut self.context()) {
+                    self.report(&location, err.to_string());
+                }
+            },
+            "track_set" => {
+                for (i, track) in elements(data).iter().enumerate() {
+                    self.track(track, &format!("{}[{}]", location, i));
+                }
+            },
+            "weighted_tracks" | "layers" => {
+                for (i, entry) in elements(data).iter().enumerate() {
+                    let location = format!("{}[{}]", location, i);
+                    match entry.field("track") {
+                        Ok(track) => self.track(track, &format!("{}.track", location)),
+                        Err(_) => self.report(&location, "missing track".to_string())
+                    }
+                    if type_name == "layers" {
+                        self.one_of(entry, "blend", &["override", "additive"], &location);
+                    }
+                }
+            },
+            "track_set_from_resource" => {
+                let resource_id = match data.translate::<String>(&mut self.context()) {
+                    Ok(id) => id,
+                    Err(err) => return self.report(&location, err.to_string())
+                };
+                let missing = match self.document {
+                    Some((document, _)) => document.resources.get(&resource_id).and_then(|r| r.downcast_ref::<Rc<TrackSet>>()).is_none(),
+                    None => false
+                };
+                if missing {
+                    self.report(&location, format!("there is no track set resource named {}", resource_id));
+                }
+            },
+            _ => self.report(&location, format!("unknown track type {}", type_name))
+        }
+    }
+}
+
+/// Checks an animation for problems, returning all of them rather than stopping at the first.
+/// When a document and entity are given, property references are resolved from that entity
+/// and resources are looked up in the document.
+pub fn validate_animation(pon: &Pon, location: &str, document: Option<(&Document, &EntityId)>) -> Vec<Diagnostic> {
+    let mut validator = Validator {
+        document: document,
+        diagnostics: vec![]
+    };
+    validator.track(pon, location);
+    validator.diagnostics
+}
+
+
+#[test]
+fn test_validate_keys() {
+    let pon = Pon::from_string(
+        "track_set [
+            key_framed { property: this.x, keys: [[0.0, 0.0], [1.0, [1.0, 2.0]], [0.5, 1.0], [0.5, 1.0]], loop: 'sometimes' },
+            key_framed { property: this.y, keys: [[0.0, 0.0], [2.0, 1.0]], curve_time: 'relative' },
+            key_framed { property: this.z, keys: [] }
+        ]").unwrap();
+    assert_eq!(validate_animation(&pon, "animation", None).iter().map(|d| d.to_string()).collect::<Vec<String>>(), vec![
+        "animation.track_set[0].key_framed.loop: expected one of forever, once, found sometimes".to_string(),
+        "animation.track_set[0].key_framed.keys[1]: value has 2 components, the previous key has 1".to_string(),
+        "animation.track_set[0].key_framed.keys[2]: time 0.5 comes before the previous key at 1".to_string(),
+        "animation.track_set[0].key_framed.keys[2]: value has 1 components, the previous key has 2".to_string(),
+        "animation.track_set[0].key_framed.keys[3]: warning: duplicate key time 0.5 makes the curve jump".to_string(),
+        "animation.track_set[1].key_framed.keys[1]: time 2 is outside 0..1 of a relative curve".to_string(),
+        "animation.track_set[2].key_framed.keys: a key framed track needs at least one key".to_string()
+    ]);
+}
+
+#[test]
+fn test_validate_spring_mass() {
+    let pon = Pon::from_string("spring { property: this.x, target: 1.0, mass: -1.0 }").unwrap();
+    assert_eq!(validate_animation(&pon, "animation", None).iter().map(|d| d.to_string()).collect::<Vec<String>>(), vec![
+        "animation.spring.mass: mass must be positive, found -1".to_string()
+    ]);
+}
+
+#[test]
+fn test_validate_timing_and_ranges() {
+    let pon = Pon::from_string(
+        "track_set [
+            fixed_value { property: this.x, value: 1.0, loop: 'always' },
+            sine { property: this.y, curve_time: 'scaled' },
+            clip_range { track: key_framed { property: this.z, keys: [[0.0, 0.0], [2.0, 1.0]], duration: 2.0 }, start: 1.5, end: 0.5 },
+            clip_range { track: key_framed { property: this.w, keys: [[0.0, 0.0], [1.0, 1.0]] }, start: 3.0 }
+        ]").unwrap();
+    assert_eq!(validate_animation(&pon, "animation", None).iter().map(|d| d.to_string()).collect::<Vec<String>>(), vec![
+        "animation.track_set[0].fixed_value.loop: expected one of forever, once, found always".to_string(),
+        "animation.track_set[1].sine.curve_time: expected one of absolute, relative, found scaled".to_string(),
+        "animation.track_set[2].clip_range.end: clip range ends at 0.5 before it starts at 1.5".to_string(),
+        "animation.track_set[3].clip_range.end: clip range ends at 1 before it starts at 3".to_string()
+    ]);
+}
+
+#[test]
+fn test_validate_valid() {
+    let pon = Pon::from_string(
+        "layers [ { track: fixed_value { property: this.x, value: 1.0 } }, { track: key_framed { property: this.y, keys: [[0.0, 0.0], [1.0, 1.0]] }, blend: 'additive' } ]").unwrap();
+    assert_eq!(validate_animation(&pon, "animation", None), vec![]);
+}
@@ -7,7 +7,9 @@ use curve_track::*;
 use std::collections::HashMap;
 use time::*;
 use track::*;
+use validate::*;
 use pyramid::pon::*;
+use pyramid::document::*;
 use animatable::*;
 
 #[derive(Debug)]
@@ -23,9 +25,12 @@ pub struct WeightedTracks {
 
 impl Track for WeightedTracks {
     fn value_at(&self, time: Duration) -> Vec<(NamedPropRef, Animatable)> {
+        self.value_at_with(time, &NoProperties)
+    }
+    fn value_at_with(&self, time: Duration, properties: &PropertySource) -> Vec<(NamedPropRef, Animatable)> {
         let mut by_props: HashMap<NamedPropRef, Animatable> = HashMap::new();
         for track in &self.tracks {
-            for update in track.track.value_at(time) {
+            for update in track.track.value_at_with(time, properties) {
                 let new_value = match by_props.get(&update.0) {
                     Some(value) => value.add_weighted(track.weight, &update.1),
                     None => update.1.weighted(track.weight)
@@ -35,11 +40,59 @@ impl Track for WeightedTracks {
         }
         by_props.into_iter().collect()
     }
+    fn duration(&self) -> Duration {
+        self.tracks.iter().map(|track| track.track.duration()).max().unwrap_or(Duration::zero())
+    }
+    fn properties(&self) -> Vec<NamedPropRef> {
+        collect_properties(self.tracks.iter().map(|track| &*track.track))
+    }
+    fn key_times(&self) -> Vec<f32> {
+        self.tracks.iter().flat_map(|track| track.track.key_times().into_iter()).collect()
+    }
+    fn advance(&mut self, time: Duration, delta: Duration, properties: &PropertySource) {
+        for track in self.tracks.iter_mut() {
+            track.track.advance(time, delta, properties);
+        }
+    }
+    fn refresh_resources(&mut self, document: &Document) -> Vec<Diagnostic> {
+        self.tracks.iter_mut().flat_map(|track| track.track.refresh_resources(document).into_iter()).collect()
+    }
+    fn is_stateful(&self) -> bool {
+        self.tracks.iter().any(|track| track.track.is_stateful())
+    }
+}
+
+impl ToPon for WeightedTracks {
+    fn to_pon(&self) -> Pon {
+        typed_pon("weighted_tracks", Pon::Array(self.tracks.iter().map(|track| object_pon(vec![
+            ("weight", track.weight.to_pon()),
+            ("track", track.track.to_pon())
+        ])).collect()))
+    }
+}
+
+impl Translatable<WeightedTrack> for Pon {
+    fn inner_translate(&self, context: &mut TranslateContext) -> Result<WeightedTrack, PonTranslateErr> {
+        Ok(WeightedTrack {
+            weight: try!(self.field_as::<f32>("weight", context)),
+            track: try!(self.field_as::<Box<Track>>("track", context))
+        })
+    }
 }
 
 impl Translatable<WeightedTracks> for Pon {
     fn inner_translate(&self, context: &mut TranslateContext) -> Result<WeightedTracks, PonTranslateErr> {
-        unimplemented!()
+        self.as_typed(|&TypedPon { ref type_name, ref data }| {
+            match type_name.as_str() {
+                "weighted_tracks" => {
+                    let tracks = try!(data.translate::<PonAutoVec<WeightedTrack>>(context));
+                    Ok(WeightedTracks {
+                        tracks: tracks.0
+                    })
+                },
+                s @ _ => Err(PonTranslateErr::UnrecognizedType(s.to_string()))
+            }
+        })
     }
 }
 
@@ -58,3 +111,14 @@ fn test_tracks() {
         (NamedPropRef::new(EntityPath::This, "y"), Animatable::new_float(25.0))
     ].sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal)));
 }
+
+#[test]
+fn test_tracks_from_pon_roundtrip() {
+    let setup: WeightedTracks = Pon::from_string(
+        "weighted_tracks [ { weight: 0.5, track: fixed_value { property: this.x, value: 2.0 } }, { weight: 0.25, track: fixed_value { property: this.x, value: 4.0 } } ]")
+        .unwrap().translate(&mut TranslateContext::empty()).unwrap();
+    assert_eq!(setup.value_at(Duration::zero()), vec![(NamedPropRef::new(EntityPath::This, "x"), Animatable::new_float(2.0))]);
+    let pon = setup.to_pon();
+    let roundtrip: WeightedTracks = pon.translate(&mut TranslateContext::empty()).unwrap();
+    assert_eq!(roundtrip.to_pon(), pon);
+}